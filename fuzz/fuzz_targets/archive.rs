@@ -0,0 +1,35 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Parse and decode every entry of an archive.
+
+#![no_main]
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+use uncbv::Archive;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(archive) = Archive::from_bytes(data.to_vec()) {
+        for entry in archive.entries() {
+            let _ = entry.block_flags();
+            let _ = entry.verify();
+            let _ = entry.decompress(&mut io::sink());
+        }
+    }
+});
@@ -0,0 +1,35 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Check the password of an encrypted archive and decrypt it.
+
+#![no_main]
+
+use std::io::Read;
+
+use libfuzzer_sys::fuzz_target;
+use uncbv::{Archive, Decryptor, check_password};
+
+const PASSWORD: &str = "password";
+
+fuzz_target!(|data: &[u8]| {
+    let _ = check_password(data, PASSWORD);
+    let mut bytes = vec![];
+    if Decryptor::new(data, PASSWORD).read_to_end(&mut bytes).is_ok() {
+        let _ = Archive::from_bytes(bytes);
+    }
+});
@@ -0,0 +1,32 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Parse the header and the file list, with every supported filename encoding.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uncbv::{HEADER_SIZE, filename_encoding, parse_file_list_with_encoding, parse_header};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = parse_header(data) {
+        for label in &["cp1252", "cp1251", "cp437"] {
+            let encoding = filename_encoding(label).unwrap();
+            let _ = parse_file_list_with_encoding(&data[HEADER_SIZE..], &header, encoding);
+        }
+    }
+});
@@ -0,0 +1,37 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Read an archive sequentially, decoding every other entry and skipping the others.
+
+#![no_main]
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+use uncbv::ArchiveReader;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut archive) = ArchiveReader::new(data) {
+        let mut index = 0;
+        while let Ok(Some(mut entry)) = archive.next_entry() {
+            if index % 2 == 0 && entry.decompress(&mut io::sink()).is_err() {
+                break;
+            }
+            index += 1;
+        }
+    }
+});
@@ -15,182 +15,605 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-//! CBV archive utility functions.
+//! CBV archive reader.
 
-use std::collections::HashSet;
-use std::ffi::OsStr;
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{self, Error, ErrorKind, Read};
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::ops::{Deref, Range};
+use std::path::{Component, Path, PathBuf};
 
+use encoding::EncodingRef;
+use encoding::all::WINDOWS_1252;
 use memmap::{Mmap, Protection};
 use nom::IResult::{self, Done, Incomplete};
 
-use cbv::{self, FileMetaData, extract_file_list, extract_files, file_list};
-use decrypt::decrypt;
+use cbv::{self, BLOCK_HEADER_SIZE, BlockError, CompressionFlags, FileMetaData, HEADER_SIZE, Header, MAGIC,
+    block_flags, raw_block};
+use compress::checksum;
+use decrypt::{DES_BLOCK_SIZE, Decryptor};
+use error::Error;
+use partial::PartialFile;
+use pool;
 
-const HEADER_SIZE: usize = 8;
+/// Kind of a file, as detected by `identify()` or `identify_with_password()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveKind {
+    /// An encrypted archive (usually with the .cbz extension), which the password decrypts.
+    Encrypted,
+    /// A file which can be an encrypted archive, but cannot be recognized without its password.
+    PossiblyEncrypted,
+    /// A CBV archive which is not encrypted.
+    Plain,
+    /// Neither a CBV archive nor an encrypted archive.
+    Unknown,
+}
 
-/// Unwrap a Done or return an error.
-macro_rules! unwrap_or_error {
-    ($val:expr, $message:expr) => {
-        match $val {
-            Done(_, filenames) => filenames,
-            IResult::Error(_) | Incomplete(_) => return Err(Error::new(ErrorKind::InvalidInput, $message)),
-        }
-    };
-    ($val:expr) => {
-        match $val {
-            Done(_, filenames) => filenames,
-            IResult::Error(_) | Incomplete(_) => return Err(Error::new(ErrorKind::InvalidInput, "Not a CBV archive")),
+/// The bytes of an archive.
+enum Data {
+    Bytes(Vec<u8>),
+    Mmap(Mmap),
+}
+
+impl Data {
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            Data::Bytes(ref bytes) => bytes,
+            Data::Mmap(ref file) => unsafe { file.as_slice() },
         }
-    };
+    }
+}
+
+/// A CBV archive.
+pub struct Archive {
+    data: Data,
+    /// Position of the compressed content of every entry in `data`.
+    entry_ranges: Vec<Range<usize>>,
+    file_list: Vec<FileMetaData>,
+    header: Header,
 }
 
-/// Ask to override a file.
-fn ask_override_file(path: &Path) -> bool {
-    if path.exists() {
-        let path = path.to_str().unwrap();
-        println!("The file {} already exists. Do you wish to override it? [y/N]", path);
-        let mut answer = String::new();
-        io::stdin().read_line(&mut answer).unwrap();
-        answer.chars().next().unwrap().to_lowercase().collect::<String>() == "y"
+impl Archive {
+    /// Open the archive at `path`.
+    /// The file is memory-mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Archive, Error> {
+        let file = File::open(path)?;
+        // NOTE: an empty file cannot be memory-mapped.
+        if file.metadata()?.len() == 0 {
+            return Err(Error::TruncatedHeader);
+        }
+        let file = Mmap::open(&file, Protection::Read)?;
+        Archive::new(Data::Mmap(file))
     }
-    else {
-        true
+
+    /// Open the encrypted archive (.cbz) at `path`.
+    /// The whole archive is decrypted in memory, so this uses as much memory as the size of the
+    /// archive: use an `ArchiveReader` over a `Decryptor` to read the entries in order with a
+    /// bounded memory.
+    /// Returns `Error::WrongPassword` if the decrypted archive does not start with the CBV magic
+    /// number. The other errors are the ones of a corrupted archive.
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, password: &str) -> Result<Archive, Error> {
+        let file = File::open(path)?;
+        let mut bytes = Vec::with_capacity(file.metadata()?.len() as usize);
+        Decryptor::new(file, password).read_to_end(&mut bytes)?;
+        Archive::from_bytes(bytes)
+            .map_err(wrong_password)
+    }
+
+    /// Create an archive from its bytes.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Archive, Error> {
+        Archive::new(Data::Bytes(bytes))
+    }
+
+    /// Read a whole archive from `reader`.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Archive, Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Archive::from_bytes(bytes)
+    }
+
+    fn new(data: Data) -> Result<Archive, Error> {
+        let (entry_ranges, header, file_list) = {
+            let bytes = data.as_slice();
+            let header = parse_header(bytes)?;
+            let file_list = parse_file_list(&bytes[HEADER_SIZE..], &header)?;
+            // The entries are stored one after the other, after the file list.
+            // NOTE: a truncated entry is detected when decompressing it.
+            let mut start = min(HEADER_SIZE + header.total_size(), bytes.len());
+            let entry_ranges = file_list.iter()
+                .map(|file| {
+                    let end = min(start.saturating_add(file.compressed_size as usize), bytes.len());
+                    let range = start .. end;
+                    start = end;
+                    range
+                })
+                .collect();
+            (entry_ranges, header, file_list)
+        };
+        Ok(Archive {
+            data,
+            entry_ranges,
+            file_list,
+            header,
+        })
+    }
+
+    /// Get an iterator over the entries of the archive.
+    pub fn entries(&self) -> Entries<'_> {
+        Entries {
+            archive: self,
+            indices: 0 .. self.file_list.len(),
+        }
+    }
+
+    /// Get the entry named `filename`, whose directories are separated by slashes.
+    /// Only this entry is decoded when it is read.
+    pub fn entry(&self, filename: &str) -> Option<Entry<'_>> {
+        self.file_list.iter()
+            .position(|file| file.filename == filename)
+            .and_then(|index| self.entry_at(index))
+    }
+
+    /// Get the entry at `index` in the file list.
+    pub fn entry_at(&self, index: usize) -> Option<Entry<'_>> {
+        let range = self.entry_ranges.get(index)?.clone();
+        Some(Entry {
+            data: &self.data.as_slice()[range.clone()],
+            metadata: &self.file_list[index],
+            offset: range.start,
+        })
+    }
+
+    /// Decompress every entry of the archive into `output_dir`.
+    pub fn extract<P: AsRef<Path>>(&self, output_dir: P) -> Result<(), Error> {
+        for entry in self.entries() {
+            entry.extract(output_dir.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Decompress every entry of the archive into `output_dir`, using `jobs` threads.
+    /// See `extract_entries()`.
+    pub fn extract_parallel<P: AsRef<Path>>(&self, output_dir: P, jobs: usize) -> Result<(), Error> {
+        let entries: Vec<_> = self.entries().collect();
+        extract_entries(&entries, output_dir, jobs)
+    }
+
+    /// Get the archive header.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Check if the archive contains no file.
+    pub fn is_empty(&self) -> bool {
+        self.file_list.is_empty()
+    }
+
+    /// Get the number of files in the archive.
+    pub fn len(&self) -> usize {
+        self.file_list.len()
+    }
+
+    /// Decode the filenames with `encoding` instead of the Windows code page 1252.
+    pub fn set_filename_encoding(&mut self, encoding: EncodingRef) -> Result<(), Error> {
+        self.file_list = parse_file_list_with_encoding(&self.data.as_slice()[HEADER_SIZE..], &self.header, encoding)?;
+        Ok(())
     }
 }
 
-/// Ask for the password.
-fn ask_password() -> String {
-    let mut password = String::new();
-    println!("Password:");
-    io::stdin().read_line(&mut password).unwrap();
-    password.pop();
-    password
+/// A file in the archive.
+/// It dereferences to its `FileMetaData`.
+#[derive(Clone, Copy)]
+pub struct Entry<'a> {
+    data: &'a [u8],
+    metadata: &'a FileMetaData,
+    /// Position of `data` in the archive.
+    offset: usize,
 }
 
-/// Ask for the password and decrypt the archive.
-/// Returns whether the archive has been decrypted or not.
-pub fn decrypt_archive(filename: &str, output: Option<String>, no_confirm: bool) -> bool {
-    let output = output.unwrap_or_else(|| {
-        let mut path = PathBuf::from(filename);
-        path.set_extension("cbv");
-        path.into_os_string().into_string().unwrap()
-    });
+impl<'a> Entry<'a> {
+    /// Convert the `error` which occurred while decoding the block at `block_index`, whose bytes are `block`.
+    fn block_error(&self, block_index: usize, block: &[u8], error: BlockError) -> Error {
+        block_error(&self.metadata.filename, block_index, self.offset_of(block), error)
+    }
 
-    let override_file = no_confirm || ask_override_file(Path::new(&output));
+    /// Get the compression flags of every block of the file, without decoding them.
+    pub fn block_flags(&self) -> Result<Vec<CompressionFlags>, Error> {
+        self.blocks()?.iter()
+            .enumerate()
+            .map(|(block_index, block)|
+                match block_flags(block) {
+                    Done(_, flags) => Ok(flags),
+                    IResult::Error(_) | Incomplete(_) => Err(self.block_error(block_index, block, BlockError::Invalid)),
+                })
+            .collect()
+    }
 
-    if override_file {
-        let password = ask_password();
-        let mut input_file = File::open(filename).unwrap();
-        {
-            let output_dir = Path::new(&output).parent().unwrap();
-            create_dir_all(output_dir).unwrap();
+    /// Split the compressed content into its blocks, without decoding them.
+    fn blocks(&self) -> Result<Vec<&'a [u8]>, Error> {
+        let mut blocks = vec![];
+        let mut input = self.data;
+        while !input.is_empty() {
+            match raw_block(input) {
+                Done(new_input, bytes) => {
+                    input = new_input;
+                    blocks.push(bytes);
+                },
+                IResult::Error(_) | Incomplete(_) => return Err(self.truncated_block(blocks.len(), input)),
+            }
         }
-        let mut file = File::create(output).unwrap();
-        decrypt(&mut input_file, &password, &mut file).unwrap();
+        Ok(blocks)
+    }
+
+    /// Decode the block at `block_index`, whose bytes are `block`.
+    fn decode_block(&self, block_index: usize, block: &[u8]) -> Result<Vec<u8>, Error> {
+        cbv::decode_block(block)
+            .map_err(|error| self.block_error(block_index, block, error))
+    }
+
+    /// Decompress the file into `output`.
+    pub fn decompress<W: Write>(&self, output: &mut W) -> Result<(), Error> {
+        io::copy(&mut self.reader(), output)?;
+        Ok(())
+    }
+
+    /// Decompress the file under `output_dir`, creating the required directories and truncating
+    /// the existing file.
+    pub fn extract<P: AsRef<Path>>(&self, output_dir: P) -> Result<(), Error> {
+        self.extract_to(self.output_path(output_dir)?)
+    }
+
+    /// Decompress the file to `path`, creating the required directories and replacing the
+    /// existing file.
+    /// The file is written in a temporary file which is renamed to `path` only if the whole file is
+    /// decoded.
+    pub fn extract_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut file = PartialFile::create(path.as_ref())?;
+        self.decompress(&mut file)?;
+        file.commit()?;
+        Ok(())
+    }
+
+    /// Get the file meta-data.
+    pub fn metadata(&self) -> &'a FileMetaData {
+        self.metadata
+    }
+
+    /// Get the position in the archive of `bytes`, which are part of the compressed content.
+    fn offset_of(&self, bytes: &[u8]) -> usize {
+        self.offset + (bytes.as_ptr() as usize - self.data.as_ptr() as usize)
+    }
+
+    /// Get the path where the file is extracted under `output_dir`.
+    /// See `FileMetaData::output_path()`.
+    pub fn output_path<P: AsRef<Path>>(&self, output_dir: P) -> Result<PathBuf, Error> {
+        self.metadata.output_path(output_dir)
     }
-    override_file
-}
 
-/// Decrypt, unarchive and decompress the files from a CBV archive.
-pub fn extract(filename: &str, output_dir: &str, no_confirm: bool) -> Result<(), Error> {
-    let output_path = Path::new(output_dir);
-    let filename =
-        if is_encrypted_archive(filename) {
-            let mut path = PathBuf::from(filename);
-            path.set_extension("cbv");
-            let new_filename = path.file_name().unwrap().to_str().unwrap();
-            try!(create_dir_all(output_dir));
-            let output_file_path = output_path.join(new_filename);
-            let output_file = output_file_path.into_os_string().into_string().unwrap();;
+    /// Decode the file in memory and check it against its meta-data: the compressed size, the
+    /// checksum of every block and the decompressed size.
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.data.len() != self.metadata.compressed_size as usize {
+            return Err(Error::CompressedSize {
+                entry: self.metadata.filename.clone(),
+                actual: self.data.len(),
+                expected: self.metadata.compressed_size,
+            });
+        }
 
-            if !decrypt_archive(filename, Some(output_file.clone()), no_confirm) {
-                return Ok(());
+        let mut decompressed_size = 0;
+        for (block_index, block) in self.blocks()?.into_iter().enumerate() {
+            let expected_checksum = block[2] as u16 | (block[3] as u16) << 8;
+            if checksum(&block[BLOCK_HEADER_SIZE..]) != expected_checksum {
+                return Err(Error::BadChecksum {
+                    entry: self.metadata.filename.clone(),
+                    block_index,
+                    offset: self.offset_of(block),
+                });
             }
-            output_file
+            decompressed_size += self.decode_block(block_index, block)?.len();
         }
-        else {
-            filename.to_string()
-        };
 
-    let file = try!(Mmap::open_path(filename, Protection::Read));
-    let bytes: &[u8] = unsafe { file.as_slice() };
-    let file_list = unwrap_or_error!(extract_file_list(bytes));
+        if decompressed_size != self.metadata.decompressed_size as usize {
+            return Err(Error::DecompressedSize {
+                entry: self.metadata.filename.clone(),
+                actual: decompressed_size,
+                expected: self.metadata.decompressed_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Get a reader decompressing the file as it is read.
+    pub fn reader(&self) -> EntryReader<'a> {
+        EntryReader {
+            block: vec![],
+            block_index: 0,
+            entry: *self,
+            input: self.data,
+            position: 0,
+        }
+    }
+
+    /// Get the error for the block at `block_index`, whose bytes `input` are shorter than its size.
+    fn truncated_block(&self, block_index: usize, input: &[u8]) -> Error {
+        Error::TruncatedBlock {
+            entry: self.metadata.filename.clone(),
+            block_index,
+            offset: self.offset_of(input),
+        }
+    }
+}
+
+impl<'a> Deref for Entry<'a> {
+    type Target = FileMetaData;
+
+    fn deref(&self) -> &FileMetaData {
+        self.metadata
+    }
+}
+
+impl FileMetaData {
+    /// Get the path where the file is extracted under `output_dir`.
+    /// The filename is normalized and a filename which would escape `output_dir` (absolute path,
+    /// drive letter, `..` component or NUL byte) is an error.
+    pub fn output_path<P: AsRef<Path>>(&self, output_dir: P) -> Result<PathBuf, Error> {
+        Ok(output_dir.as_ref().join(relative_path(&self.filename)?))
+    }
+}
+
+/// Iterator over the entries of an archive.
+pub struct Entries<'a> {
+    archive: &'a Archive,
+    indices: Range<usize>,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        self.indices.next().and_then(|index| self.archive.entry_at(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Entries<'a> {
+}
+
+/// Reader over the decompressed content of an entry.
+/// The blocks are only decoded when the previous one has been completely read.
+pub struct EntryReader<'a> {
+    block: Vec<u8>,
+    /// Index of the next block.
+    block_index: usize,
+    entry: Entry<'a>,
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> EntryReader<'a> {
+    /// Decode the next block.
+    /// Returns false when there is no more block.
+    fn next_block(&mut self) -> Result<bool, Error> {
+        if self.input.is_empty() {
+            return Ok(false);
+        }
+        match raw_block(self.input) {
+            Done(new_input, bytes) => {
+                self.block = self.entry.decode_block(self.block_index, bytes)?;
+                self.block_index += 1;
+                self.input = new_input;
+                self.position = 0;
+                Ok(true)
+            },
+            IResult::Error(_) | Incomplete(_) => Err(self.entry.truncated_block(self.block_index, self.input)),
+        }
+    }
+}
+
+impl<'a> Read for EntryReader<'a> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        while self.position == self.block.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let size = min(buffer.len(), self.block.len() - self.position);
+        buffer[..size].copy_from_slice(&self.block[self.position .. self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
 
-    let first_file_path = output_path.join(&file_list[0].filename);
-    let override_file = no_confirm || ask_override_file(first_file_path.as_path());
+/// Convert the `error` which occurred while decoding the block at `block_index` of `entry`, found at
+/// `offset` in the archive.
+pub fn block_error(entry: &str, block_index: usize, offset: usize, error: BlockError) -> Error {
+    let entry = entry.to_string();
+    match error {
+        BlockError::Huffman => Error::HuffmanTree { entry, block_index, offset },
+        BlockError::Invalid => Error::BadBlock { entry, block_index, offset },
+    }
+}
 
-    if override_file {
-        try!(init_output(&file_list, output_dir));
-        // TODO: do not parse again the file list.
-        unwrap_or_error!(extract_files(bytes, output_dir))
+/// Check if `password` is the password of the encrypted archive read from `reader`, by decrypting
+/// and parsing its header and its file list.
+pub fn check_password<R: Read>(reader: R, password: &str) -> Result<bool, Error> {
+    let mut reader = Decryptor::new(reader, password);
+    let mut buffer = [0; HEADER_SIZE];
+    match reader.read_exact(&mut buffer) {
+        Ok(()) => (),
+        Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        Err(error) => return Err(error.into()),
     }
+    let header = match parse_header(&buffer) {
+        Ok(header) => header,
+        Err(_) => return Ok(false),
+    };
 
-    Ok(())
+    let mut buffer = vec![0; header.total_size()];
+    match reader.read_exact(&mut buffer) {
+        Ok(()) => (),
+        Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        Err(error) => return Err(error.into()),
+    }
+    let valid = match parse_file_list(&buffer, &header) {
+        Ok(file_list) => file_list.iter().all(|file| file.compressed_size >= 0 && file.decompressed_size >= 0),
+        Err(_) => false,
+    };
+    Ok(valid)
 }
 
-/// Extract the filenames from the archive.
-pub fn get_file_list(filename: &str) -> Result<Vec<FileMetaData>, Error> {
-    if is_encrypted_archive(filename) {
-        let password = ask_password();
-        let mut cbv_output = vec![];
-        let mut buffer = [0; HEADER_SIZE];
-        let mut file = try!(File::open(filename));
+/// Decompress `entries` into `output_dir`, using `jobs` threads.
+/// The blocks of every entry are decompressed concurrently and written in order, so the files are
+/// identical to the ones written by `Entry::extract()`.
+pub fn extract_entries<P: AsRef<Path>>(entries: &[Entry], output_dir: P, jobs: usize) -> Result<(), Error> {
+    let files = entries.iter()
+        .map(|entry| Ok((*entry, entry.output_path(output_dir.as_ref())?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    extract_entries_to(&files, jobs)
+}
 
-        try!(file.read(&mut buffer[..HEADER_SIZE]));
-        try!(decrypt(buffer.as_ref(), &password, &mut cbv_output));
+/// Decompress every entry of `files` to the path following it, using `jobs` threads.
+pub fn extract_entries_to(files: &[(Entry, PathBuf)], jobs: usize) -> Result<(), Error> {
+    if jobs <= 1 {
+        for (entry, path) in files {
+            entry.extract_to(path)?;
+        }
+        return Ok(());
+    }
 
-        let header = unwrap_or_error!(cbv::header(&cbv_output), "Wrong password");
-        cbv_output.clear();
+    pool::decode_blocks(jobs, |decoder| {
+        for (entry, path) in files {
+            let mut file = PartialFile::create(path)?;
+            let mut blocks = decoder.start_entry(&entry.filename);
+            for block in entry.blocks()? {
+                decoder.send(&mut blocks, entry.offset_of(block), Cow::Borrowed(block), &mut file)?;
+            }
+            decoder.finish_entry(&blocks, &mut file)?;
+            file.commit()?;
+        }
+        Ok(())
+    })
+}
 
-        let file_list_len = header.total_size();
-        let mut buffer = vec![0; file_list_len];
-        try!(file.read_exact(&mut buffer));
-        try!(decrypt(buffer.as_slice(), &password, &mut cbv_output));
+/// Detect the kind of the file at `path` from its content, whatever its extension is.
+/// Since an encrypted archive cannot be decoded without its password, a file which does not start
+/// with the CBV magic number is only considered to be possibly encrypted if its size is a non-zero
+/// multiple of the DES block size: use `identify_with_password()` to recognize it.
+pub fn identify<P: AsRef<Path>>(path: P) -> Result<ArchiveKind, Error> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = file.take(HEADER_SIZE as u64);
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    if let Ok(header) = parse_header(&bytes) {
+        reader.set_limit(header.total_size() as u64);
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() == HEADER_SIZE + header.total_size() && parse_file_list(&bytes[HEADER_SIZE..], &header).is_ok() {
+            return Ok(ArchiveKind::Plain);
+        }
+    }
 
-        let result = file_list(&cbv_output, header);
+    if !bytes.starts_with(&MAGIC) && size > 0 && size % DES_BLOCK_SIZE as u64 == 0 {
+        Ok(ArchiveKind::PossiblyEncrypted)
+    }
+    else {
+        Ok(ArchiveKind::Unknown)
+    }
+}
 
-        Ok(unwrap_or_error!(result))
+/// Detect the kind of the file at `path` like `identify()`, but recognize a possibly encrypted
+/// archive as encrypted if `password` decrypts its first block into the CBV magic number, and as
+/// unknown otherwise.
+pub fn identify_with_password<P: AsRef<Path>>(path: P, password: &str) -> Result<ArchiveKind, Error> {
+    let kind = identify(&path)?;
+    if kind != ArchiveKind::PossiblyEncrypted {
+        return Ok(kind);
+    }
+    if decrypts_magic(File::open(path)?, password)? {
+        Ok(ArchiveKind::Encrypted)
     }
     else {
-        let file = try!(Mmap::open_path(filename, Protection::Read));
-        let bytes: &[u8] = unsafe { file.as_slice() };
-        Ok(unwrap_or_error!(extract_file_list(bytes)))
+        Ok(ArchiveKind::Unknown)
+    }
+}
+
+/// Check if `password` decrypts the first block of `reader` into the CBV magic number.
+fn decrypts_magic<R: Read>(reader: R, password: &str) -> Result<bool, Error> {
+    let mut block = [0; DES_BLOCK_SIZE];
+    match Decryptor::new(reader, password).read_exact(&mut block) {
+        Ok(()) => Ok(block.starts_with(&MAGIC)),
+        Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Get the error of an archive decrypted with `password`: a decrypted header without the CBV magic
+/// number means that the password is wrong.
+fn wrong_password(error: Error) -> Error {
+    match error {
+        Error::BadMagic => Error::WrongPassword,
+        error => error,
     }
 }
 
-/// Create the required directories and truncate the existing files.
-fn init_output(file_list: &[FileMetaData], output_dir: &str) -> Result<(), Error> {
-    let output_path = Path::new(output_dir);
-    let mut directories = HashSet::new();
-    let mut files = vec![];
+/// Normalize `filename` into a path relative to the output directory, rejecting the filenames
+/// which could escape it.
+fn relative_path(filename: &str) -> Result<PathBuf, Error> {
+    let unsafe_filename = |reason| Error::UnsafeFilename { filename: filename.to_string(), reason };
 
-    for file in file_list {
-        let file_path = output_path.join(&file.filename);
-        let directory = file_path.parent().unwrap().to_path_buf();
-        directories.insert(directory);
-        files.push(file_path);
+    if filename.contains('\0') {
+        return Err(unsafe_filename("NUL byte"));
+    }
+    if filename.starts_with('/') {
+        return Err(unsafe_filename("absolute path"));
+    }
+    let bytes = filename.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return Err(unsafe_filename("drive letter"));
     }
 
-    for directory in directories {
-        try!(create_dir_all(directory));
+    let mut path = PathBuf::new();
+    for component in filename.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => return Err(unsafe_filename("parent directory")),
+            component => path.push(component),
+        }
     }
+    // NOTE: a component could still be interpreted as a root or a prefix on some platforms.
+    if path.as_os_str().is_empty() || path.components().any(|component| !matches!(component, Component::Normal(_))) {
+        return Err(unsafe_filename("invalid path"));
+    }
+    Ok(path)
+}
 
-    for file in files {
-        try!(OpenOptions::new()
-             .create(true)
-             .write(true)
-             .truncate(true)
-             .open(&file));
+/// Parse the archive header from the first `HEADER_SIZE` bytes of an archive.
+pub fn parse_header(bytes: &[u8]) -> Result<Header, Error> {
+    match cbv::header(bytes) {
+        Done(_, header) => Ok(header),
+        IResult::Error(_) => Err(Error::BadMagic),
+        Incomplete(_) => Err(Error::TruncatedHeader),
     }
+}
 
-    Ok(())
+/// Parse the file list following the archive header.
+/// The filenames are decoded from the Windows code page 1252.
+pub fn parse_file_list(bytes: &[u8], header: &Header) -> Result<Vec<FileMetaData>, Error> {
+    parse_file_list_with_encoding(bytes, header, WINDOWS_1252)
 }
 
-/// Check if the file extension belongs to an encrypted CBV archive (.cbz).
-fn is_encrypted_archive(filename: &str) -> bool {
-    let path = Path::new(filename);
-    path.extension() == Some(OsStr::new("cbz"))
+/// Parse the file list following the archive header, decoding the filenames with `encoding`.
+pub fn parse_file_list_with_encoding(bytes: &[u8], header: &Header, encoding: EncodingRef)
+    -> Result<Vec<FileMetaData>, Error>
+{
+    match cbv::file_list(bytes, header.clone(), encoding) {
+        Done(_, file_list) => Ok(file_list),
+        IResult::Error(_) | Incomplete(_) if bytes.len() < header.total_size() => Err(Error::TruncatedFileList),
+        IResult::Error(_) | Incomplete(_) => Err(Error::BadFileList),
+    }
 }
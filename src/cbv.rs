@@ -17,58 +17,66 @@
 
 //! CBV file format parser.
 
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use encoding::{DecoderTrap, Encoding};
-use encoding::all::WINDOWS_1252;
+use encoding::{DecoderTrap, EncodingRef};
 use huffman;
-use nom::{be_u16, le_i32, le_u8, le_u16};
+use nom::{be_u16, le_i32, le_u8, le_u16, le_u32};
+use nom::ErrorKind;
 use nom::IResult::{self, Done};
 
-/// Create the node in the specified direction if it does not exist.
-macro_rules! create_node_if_not_exist {
-    ($node:expr, $previous:expr, $dir:ident) => {{
-        let node = unsafe { &mut *($node as *mut huffman::Tree) };
-        let previous = unsafe { &mut *($previous as *mut huffman::Tree) };
-        if let Some(ref inner_node) = node.$dir {
-            &**inner_node as *const huffman::Tree
-        }
-        else {
-            let node = Box::new($crate::huffman::Tree::new());
-            previous.$dir = Some(node);
-            &**previous.$dir.as_ref().unwrap() as *const huffman::Tree
-        }
-    }};
+/// Size of the block size and checksum in front of every block.
+pub const BLOCK_HEADER_SIZE: usize = 4;
+
+/// Size of the archive header, in bytes.
+pub const HEADER_SIZE: usize = 8;
+
+/// Magic number at the beginning of the archive header.
+pub const MAGIC: [u8; 2] = [0x08, 0x00];
+
+/// Reason why a block cannot be decoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockError {
+    /// The Huffman tree is truncated or the block contains a code which is not in it.
+    Huffman,
+    /// The compression flag is unknown or the block refers to missing bytes.
+    Invalid,
 }
 
 /// Block compression flags.
-struct CompressionFlags {
-    compressed: bool,
-    huffman_encoded: bool
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionFlags {
+    /// The block is compressed using run-length encoding and backward references.
+    pub compressed: bool,
+    /// The block is Huffman-encoded.
+    pub huffman_encoded: bool,
 }
 
 /// File meta-data.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FileMetaData {
     pub compressed_size: i32,
     pub decompressed_size: i32,
     pub filename: String,
+    /// Modification time of the file, if the record contains a valid one.
+    pub modified: Option<SystemTime>,
 }
 
 impl FileMetaData {
-    fn new(filename: String, compressed_size: i32, decompressed_size: i32) -> FileMetaData {
+    fn new(filename: String, compressed_size: i32, decompressed_size: i32, modified: Option<SystemTime>)
+        -> FileMetaData
+    {
         FileMetaData {
-            compressed_size: compressed_size,
-            decompressed_size: decompressed_size,
-            filename: filename.to_string(),
+            compressed_size,
+            decompressed_size,
+            filename,
+            modified,
         }
     }
 }
 
 /// CBV archive header.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Header {
     file_count: usize,
     filename_len: u8,
@@ -78,27 +86,44 @@ impl Header {
     fn new(file_count: u16, filename_len: u8) -> Header {
         Header {
             file_count: file_count as usize,
-            filename_len: filename_len,
+            filename_len,
         }
     }
 
+    /// Get the number of files in the archive.
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
+
+    /// Get the size of a file record in the file list.
+    pub fn filename_len(&self) -> usize {
+        self.filename_len as usize
+    }
+
     /// Get the total size of the file list in the header.
     pub fn total_size(&self) -> usize {
         self.file_count * self.filename_len as usize
     }
 }
 
-/// Parse a compressed block.
-named_args!(block<'a>(file: &FileMetaData, output_dir: &str) <()>,
-    chain!
-        ( block_size: le_u16
-        ~ le_u16 // NOTE: unknown bytes.
-        ~ flat_map!(
-              take!(block_size),
-              apply!(extract_block, file, output_dir)
-          )
-        , || ()
-        )
+/// Parse a compressed block without decompressing it and return its bytes.
+named!(pub raw_block,
+    recognize!(do_parse!(
+        block_size: le_u16 >>
+        le_u16 >>
+        take!(block_size) >>
+        ()
+    ))
+);
+
+/// Parse the compression flags of a block without decompressing it.
+named!(pub block_flags <CompressionFlags>,
+    do_parse!(
+        le_u16 >>
+        le_u16 >>
+        flags: compression_flag >>
+        (flags)
+    )
 );
 
 /// Parse the compression flag.
@@ -110,57 +135,84 @@ named!(compression_flag <CompressionFlags>,
         )
 );
 
+/// Decode and decompress a block parsed by `raw_block`.
+pub fn decode_block(bytes: &[u8]) -> Result<Vec<u8>, BlockError> {
+    let input = bytes.get(BLOCK_HEADER_SIZE..).ok_or(BlockError::Invalid)?;
+    let (input, flag) =
+        match compression_flag(input) {
+            Done(input, flag) => (input, flag),
+            _ => return Err(BlockError::Invalid),
+        };
+    let content =
+        match parse_if_else!(input, flag.huffman_encoded, huffman, slice_to_vec) {
+            Done(_, content) => content,
+            _ => return Err(BlockError::Huffman),
+        };
+    if flag.compressed {
+        decompress_block(content).ok_or(BlockError::Invalid)
+    }
+    else {
+        Ok(content)
+    }
+}
+
 /// Decompress a block.
-fn decompress_block(input: Vec<u8>) -> Vec<u8> {
+/// Returns `None` if the block refers to missing bytes.
+fn decompress_block(input: Vec<u8>) -> Option<Vec<u8>> {
     let mut result = vec![];
 
     let mut input = &input[..];
 
     'block_loop:
     while !input.is_empty() {
-        let (new_input, mut code_bytes) = le_u16(input).unwrap();
+        let (new_input, mut code_bytes) =
+            match le_u16(input) {
+                Done(new_input, code_bytes) => (new_input, code_bytes),
+                _ => return None,
+            };
         input = new_input;
 
         for _ in 0 .. 16 {
             let coded = (code_bytes & 0x8000) != 0;
+            let current_byte = *input.first()? as usize;
             if coded {
-                let current_byte = input[0] as usize;
                 let high = current_byte >> 4;
                 let low = current_byte & 0xF;
                 if high == 0 {
                     // Run-length decoding.
                     let size = low + 3;
-                    result.append(&mut vec![input[1]; size]);
+                    result.append(&mut vec![*input.get(1)?; size]);
                 }
                 else if high == 1 {
                     // Run-length decoding with bigger size.
-                    let size = low + ((input[1] as usize) << 4) + 0x13;
-                    result.append(&mut vec![input[2]; size]);
+                    let size = low + ((*input.get(1)? as usize) << 4) + 0x13;
+                    result.append(&mut vec![*input.get(2)?; size]);
                     input = &input[1..];
                 }
                 else {
                     // Copy content already seen in the file (backward reference).
                     // Get the offset and the length.
-                    let offset = ((input[1] as usize) << 4) + low + 3;
+                    let offset = ((*input.get(1)? as usize) << 4) + low + 3;
                     let size =
                         if high == 2 {
-                            let size = (input[2] as usize) + 0x10;
+                            let size = (*input.get(2)? as usize) + 0x10;
                             input = &input[1..];
-                            size as usize
+                            size
                         }
                         else {
                             high
                         };
-                    let current_position = result.len();
-                    let start = current_position - offset;
-                    let end = start + size;
-                    let mut backward_reference = result[start .. end].iter().cloned().collect();
-                    result.append(&mut backward_reference);
+                    let start = result.len().checked_sub(offset)?;
+                    // NOTE: the reference can overlap the content being decompressed (when the
+                    // size is greater than the offset), so it is copied byte by byte.
+                    for index in start .. start + size {
+                        result.push(result[index]);
+                    }
                 }
                 input = &input[1..];
             }
             else {
-                result.push(input[0]);
+                result.push(current_byte as u8);
             }
             input = &input[1..];
             if input.is_empty() {
@@ -169,137 +221,118 @@ fn decompress_block(input: Vec<u8>) -> Vec<u8> {
             code_bytes <<= 1;
         }
     }
-    result
+    Some(result)
 }
 
-/// Extract, decode and decompress a block.
-named_args!(extract_block<'a>(file: &FileMetaData, output_dir: &str) <()>,
-    chain!
-        ( flag: compression_flag
-        ~ result: map!(
-                parse_if_else!(flag.huffman_encoded, huffman, slice_to_vec),
-                |new_input|
-                    if flag.compressed {
-                        decompress_block(new_input)
-                    }
-                    else {
-                        new_input
-                    }
-            )
-        , || {
-            let path = Path::new(output_dir).join(&file.filename);
-            let mut file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(path)
-                .unwrap();
-
-            file.write_all(&result).unwrap();
-        }
-        )
-);
-
-/// Extract a file from the archive.
-named_args!(extract_file<'a>(file: FileMetaData, output_dir: &str) <()>,
-    flat_map!(
-        take!(file.compressed_size),
-        fold_many1!(apply!(block, &file, output_dir), (), |_, _| ())
-    )
-);
-
-/// Extract the files from the archive.
-named_args!(pub extract_files<'a>(output_dir: &str) <()>,
-    chain!
-        ( files: extract_file_list
-        ~ foreach!(files, file => apply!(extract_file, file, output_dir))
-        , || ()
-        )
-);
-
-/// Parse only the filenames from the archive.
-named!(pub extract_file_list < Vec<FileMetaData> >,
-    chain!
-        ( header: header
-        ~ file_list: apply!(file_list, header)
-        , || file_list
-        )
-);
-
-/// Parse a null-terminated String as a filename.
-named!(filename <String>,
+/// Parse a null-terminated String as a filename, decoded with `encoding`.
+named_args!(filename(encoding: EncodingRef) <String>,
     map!(
         flat_map!(
             take!(132),
             take_while!(is_not_zero)
         ),
-        bytes_to_filename
+        |bytes| bytes_to_filename(bytes, encoding)
     )
 );
 
-/// Parse the file list.
-named_args!(pub file_list(header: Header) < Vec<FileMetaData> >,
+/// Parse the file list, decoding the filenames with `encoding`.
+named_args!(pub file_list(header: Header, encoding: EncodingRef) < Vec<FileMetaData> >,
     count!(
         flat_map!(
             take!(header.filename_len),
-            file_metadata
+            apply!(file_metadata, encoding)
         ),
         header.file_count
     )
 );
 
 /// Parse the file metadata (name and sizes).
-named!(file_metadata <FileMetaData>,
-    chain!
-        ( filename: filename
-        ~ compressed_size: le_i32
-        ~ decompressed_size: le_i32
-        , || FileMetaData::new(filename, compressed_size, decompressed_size)
-        )
+named_args!(file_metadata(encoding: EncodingRef) <FileMetaData>,
+    do_parse!(
+        filename: apply!(filename, encoding) >>
+        compressed_size: le_i32 >>
+        decompressed_size: le_i32 >>
+        modified: opt!(complete!(modification_time)) >>
+        (FileMetaData::new(filename, compressed_size, decompressed_size, modified))
+    )
+);
+
+/// Parse the modification time following the sizes in a file record.
+named!(modification_time <SystemTime>,
+    map_opt!(
+        do_parse!(
+            le_u8 >> // NOTE: unknown byte.
+            date: le_u32 >>
+            time: le_u32 >>
+            ((date, time))
+        ),
+        |(date, time)| date_time_to_system_time(date, time)
+    )
 );
 
 /// Parse a CBV file header.
 named!(pub header <Header>,
-    chain!
-        ( tag!(&[0x08, 0x00]) // CBV magic number.
-        ~ file_count: le_u16
-        ~ filename_len: le_u8
-        ~ take!(3) // NOTE: unknown bytes.
-        , || Header::new(file_count, filename_len)
-        )
+    do_parse!(
+        tag!(&MAGIC) >>
+        file_count: le_u16 >>
+        filename_len: le_u8 >>
+        take!(3) >> // NOTE: unknown bytes.
+        (Header::new(file_count, filename_len))
+    )
 );
 
 /// Decode a huffman-encoded block.
 named!(huffman < Vec<u8> >,
-    chain!
-        ( decompressed_size: be_u16
-        ~ result: bits!(
-              chain!
-                  ( tree: huffman_tree
-                  ~ result: apply!(huffman_decode, tree, decompressed_size as usize)
-                  , || result
-                  )
-          )
-        , || result
-        )
+    do_parse!(
+        decompressed_size: be_u16 >>
+        result: bits!(
+            do_parse!(
+                tree: huffman_tree >>
+                result: apply!(huffman_decode, tree, decompressed_size as usize) >>
+                (result)
+            )
+        ) >>
+        (result)
+    )
 );
 
 /// Decode a huffman-encoded block using `tree` up to `decompressed_size`.
+/// The decoding fails if the input contains a code which is not in the tree.
 fn huffman_decode((input, offset): (&[u8], usize), tree: huffman::Tree, decompressed_size: usize) -> IResult<(&[u8], usize), Vec<u8>> {
-    let (new_input, old_input) = input.split_at(0);
-    Done((new_input, 0), huffman::decode_with_offset(old_input, offset as u8, &tree, decompressed_size))
+    let mut result = vec![];
+    let mut node = &tree;
+    let bits = input.iter()
+        .flat_map(|&byte| (0 .. 8).rev().map(move |index| (byte >> index) & 1))
+        .skip(offset);
+    for bit in bits {
+        if result.len() == decompressed_size {
+            break;
+        }
+
+        let child = if bit == 1 { &node.right } else { &node.left };
+        node =
+            match *child {
+                Some(ref child) => child,
+                None => return IResult::Error(ErrorKind::Custom(0)),
+            };
+        if let Some(byte) = node.value {
+            result.push(byte);
+            node = &tree;
+        }
+    }
+    let (new_input, _) = input.split_at(input.len());
+    Done((new_input, 0), result)
 }
 
 /// Decode a huffman tree.
 named!(huffman_tree((&[u8], usize)) -> huffman::Tree,
     map!(
-        count_fixed!(
-            (usize, u16),
-            chain!
-                ( len: take_bits!(usize, 4)
-                ~ bits: take_bits!(u16, len)
-                , || (len, bits)
-                ),
+        count!(
+            do_parse!(
+                len: take_bits!(usize, 4) >>
+                bits: take_bits!(u16, len) >>
+                ((len, bits))
+            ),
             256
         ),
         create_huffman_tree
@@ -307,43 +340,58 @@ named!(huffman_tree((&[u8], usize)) -> huffman::Tree,
 );
 
 /// Convert the bytes reprensenting the filename into a String, replacing the backslashes by
-/// slashes and converting the filename to UTF-8.
-fn bytes_to_filename(bytes: &[u8]) -> String {
-    let mut string = WINDOWS_1252.decode(bytes, DecoderTrap::Strict)
-        .unwrap(); // NOTE: The filename should be valid.
+/// slashes and converting the filename from `encoding` to UTF-8.
+/// The bytes which are not valid in `encoding` are replaced by U+FFFD.
+fn bytes_to_filename(bytes: &[u8], encoding: EncodingRef) -> String {
+    let mut string = encoding.decode(bytes, DecoderTrap::Replace)
+        .unwrap(); // NOTE: decoding with replacement never fails.
     replace_backslash_by_slash(&mut string);
     string
 }
 
-/// Create a Huffman tree from an array.
-fn create_huffman_tree(values: [(usize, u16); 256]) -> huffman::Tree {
-    let tree = huffman::Tree::new();
+/// Create a Huffman tree from the code lengths and bits of every byte value.
+fn create_huffman_tree(values: Vec<(usize, u16)>) -> huffman::Tree {
+    let mut tree = huffman::Tree::new();
     for (value, &(length, bits)) in values.iter().enumerate() {
         if length > 0 {
-            let mut previous = &tree as *const huffman::Tree;
-            let mut node = &tree as *const huffman::Tree;
+            let mut node = &mut tree;
             let mut bits = bits << (16 - length);
 
             for _ in 0 .. length {
-                node =
-                    if (bits & 0x8000) == 0 {
-                        create_node_if_not_exist!(node, previous, left)
-                    }
-                    else {
-                        create_node_if_not_exist!(node, previous, right)
-                    };
-
-                previous = node;
+                let child = if (bits & 0x8000) == 0 { &mut node.left } else { &mut node.right };
+                node = child.get_or_insert_with(|| Box::new(huffman::Tree::new()));
                 bits <<= 1;
             }
-            let node_ref = unsafe { &mut *(node as *mut huffman::Tree) };
-            node_ref.value = Some(value as u8);
+            node.value = Some(value as u8);
         }
     }
 
     tree
 }
 
+/// Convert the date (YYYYMMDD) and time (HHMMSScc) of a file record to a time, assuming they are
+/// in UTC.
+fn date_time_to_system_time(date: u32, time: u32) -> Option<SystemTime> {
+    let (year, month, day) = (date / 10_000, date / 100 % 100, date % 100);
+    let (hours, minutes, seconds, centiseconds) = (time / 1_000_000, time / 10_000 % 100, time / 100 % 100, time % 100);
+    if year < 1970 || !(1 ..= 12).contains(&month) || !(1 ..= 31).contains(&day) || hours >= 24 || minutes >= 60 ||
+        seconds >= 60
+    {
+        return None;
+    }
+
+    // Convert the civil date to a number of days since the epoch.
+    let year = (if month <= 2 { year - 1 } else { year }) as u64;
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as u64 + 9) % 12) + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hours as u64 * 3600 + minutes as u64 * 60 + seconds as u64;
+    Some(UNIX_EPOCH + Duration::new(seconds, centiseconds * 10_000_000))
+}
+
 /// Check if the byte is different than zero.
 fn is_not_zero(byte: u8) -> bool {
     byte != 0
@@ -361,5 +409,5 @@ fn replace_backslash_by_slash(string: &mut String) {
 
 /// Convert a slice to a vector.
 fn slice_to_vec<T: Clone>(slice: &[T]) -> Vec<T> {
-    slice.iter().cloned().collect::<Vec<T>>()
+    slice.to_vec()
 }
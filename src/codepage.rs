@@ -0,0 +1,70 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Code pages of the filenames stored in the archives.
+
+use encoding::EncodingRef;
+use encoding::codec::singlebyte::SingleByteEncoding;
+use encoding::label::encoding_from_whatwg_label;
+
+/// The DOS code page 437, which is not supported by the encoding crate.
+static CP437: SingleByteEncoding = SingleByteEncoding {
+    name: "cp437",
+    whatwg_name: None,
+    index_forward: cp437_forward,
+    index_backward: cp437_backward,
+};
+
+/// Characters of the bytes 0x80 to 0xFF in the code page 437.
+static CP437_TABLE: [u16; 128] = [
+    0x00C7, 0x00FC, 0x00E9, 0x00E2, 0x00E4, 0x00E0, 0x00E5, 0x00E7,
+    0x00EA, 0x00EB, 0x00E8, 0x00EF, 0x00EE, 0x00EC, 0x00C4, 0x00C5,
+    0x00C9, 0x00E6, 0x00C6, 0x00F4, 0x00F6, 0x00F2, 0x00FB, 0x00F9,
+    0x00FF, 0x00D6, 0x00DC, 0x00A2, 0x00A3, 0x00A5, 0x20A7, 0x0192,
+    0x00E1, 0x00ED, 0x00F3, 0x00FA, 0x00F1, 0x00D1, 0x00AA, 0x00BA,
+    0x00BF, 0x2310, 0x00AC, 0x00BD, 0x00BC, 0x00A1, 0x00AB, 0x00BB,
+    0x2591, 0x2592, 0x2593, 0x2502, 0x2524, 0x2561, 0x2562, 0x2556,
+    0x2555, 0x2563, 0x2551, 0x2557, 0x255D, 0x255C, 0x255B, 0x2510,
+    0x2514, 0x2534, 0x252C, 0x251C, 0x2500, 0x253C, 0x255E, 0x255F,
+    0x255A, 0x2554, 0x2569, 0x2566, 0x2560, 0x2550, 0x256C, 0x2567,
+    0x2568, 0x2564, 0x2565, 0x2559, 0x2558, 0x2552, 0x2553, 0x256B,
+    0x256A, 0x2518, 0x250C, 0x2588, 0x2584, 0x258C, 0x2590, 0x2580,
+    0x03B1, 0x00DF, 0x0393, 0x03C0, 0x03A3, 0x03C3, 0x00B5, 0x03C4,
+    0x03A6, 0x0398, 0x03A9, 0x03B4, 0x221E, 0x03C6, 0x03B5, 0x2229,
+    0x2261, 0x00B1, 0x2265, 0x2264, 0x2320, 0x2321, 0x00F7, 0x2248,
+    0x00B0, 0x2219, 0x00B7, 0x221A, 0x207F, 0x00B2, 0x25A0, 0x00A0,
+];
+
+fn cp437_backward(code: u32) -> u8 {
+    CP437_TABLE.iter()
+        .position(|&character| character as u32 == code)
+        .map_or(0, |index| index as u8 + 0x80)
+}
+
+fn cp437_forward(code: u8) -> u16 {
+    CP437_TABLE[(code - 0x80) as usize]
+}
+
+/// Get the encoding of the filenames from its name, like `cp1252` (the default), `cp1251`,
+/// `cp1250` or `cp437`.
+pub fn filename_encoding(label: &str) -> Option<EncodingRef> {
+    let label = label.trim().to_ascii_lowercase();
+    match label.as_str() {
+        "437" | "cp437" | "ibm437" => Some(&CP437),
+        _ => encoding_from_whatwg_label(&label),
+    }
+}
@@ -0,0 +1,696 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! uncbv commands.
+
+use std::error;
+use std::ffi::OsStr;
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{File, metadata, read_dir};
+use std::io::{self, BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use glob::Pattern;
+use uncbv::{Archive, ArchiveKind, ArchiveReader, ArchiveWriter, Decryptor, EncodingRef, Error as ArchiveError,
+    FileMetaData, HEADER_SIZE, PartialFile, check_password, decrypt, encrypt, identify, identify_with_password, parse_header};
+
+use output::{BlockRecord, ErrorRecord, FileRecord, Format, IdentifyRecord, InfoRecord, TestRecord, print_error,
+    print_identify, print_info, print_list, print_test, unix_time};
+use password::PasswordSource;
+
+/// Filename of the archive read from stdin.
+const STDIN_FILENAME: &str = "-";
+
+/// Error returned when the command line cannot be executed, like a pattern matching no file.
+#[derive(Debug)]
+pub struct UsageError {
+    message: String,
+}
+
+impl Display for UsageError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.message)
+    }
+}
+
+impl error::Error for UsageError {
+}
+
+/// Add the files of `directory` to `files`, naming them relatively to `root`, except the file
+/// `excluded`.
+fn add_directory(files: &mut Vec<(String, PathBuf)>, directory: &Path, root: &Path, excluded: Option<&Path>)
+    -> Result<(), Error>
+{
+    let mut paths = vec![];
+    for entry in read_dir(directory)? {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            add_directory(files, &path, root, excluded)?;
+        }
+        else if !is_excluded(&path, excluded) {
+            let filename = path.strip_prefix(root).unwrap();
+            let filename = filename.to_str()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid filename"))?;
+            files.push((filename.replace('\\', "/"), path));
+        }
+    }
+    Ok(())
+}
+
+/// Answer to the question asking to override a file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Answer {
+    /// Override this file and every next one.
+    All,
+    No,
+    /// Override neither this file nor any next one.
+    None,
+    Yes,
+}
+
+/// What to do with the extracted files which already exist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverwritePolicy {
+    /// Ask for every existing file.
+    Ask,
+    /// Keep the existing files which are not older than the files in the archive.
+    KeepNewer,
+    Overwrite,
+    /// Extract the files under a new name, with a numeric suffix.
+    Rename,
+    SkipExisting,
+}
+
+/// Get the password of the archive, or `None` if it is not recognized as a possibly encrypted
+/// archive.
+fn archive_password<W: Write>(filename: &str, password: &PasswordSource, prompt_output: W)
+    -> Result<Option<String>, Error>
+{
+    match identify(filename)? {
+        ArchiveKind::Encrypted | ArchiveKind::PossiblyEncrypted => Ok(Some(password.find(filename, prompt_output)?)),
+        ArchiveKind::Plain | ArchiveKind::Unknown => Ok(None),
+    }
+}
+
+/// Ask to override a file.
+/// Returns an error if the file exists and the user does not want to override it.
+fn ask_override_file(path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        println!("The file {} already exists. Do you wish to override it? [y/N]", path.display());
+        if !matches!(read_answer(), Answer::All | Answer::Yes) {
+            return Err(Error::new(ErrorKind::AlreadyExists,
+                format!("The file {} already exists and was not overwritten", path.display())));
+        }
+    }
+    Ok(())
+}
+
+/// Write the decompressed content of the files matching one of `patterns` (or every file if there
+/// is none) and none of `excludes` to stdout.
+pub fn cat(filename: &str, patterns: &[String], excludes: &[String], encoding: EncodingRef, password: &PasswordSource)
+    -> Result<(), Error>
+{
+    // NOTE: the prompt is not written to stdout to keep the output clean.
+    let (mut archive, _) = open_stream_archive(filename, encoding, password, io::stderr())?;
+    let selected = select_files(archive.file_list(), patterns, excludes)?;
+
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    let write_files = || {
+        for selected in selected {
+            let mut entry =
+                match archive.next_entry()? {
+                    Some(entry) => entry,
+                    None => break,
+                };
+            if selected {
+                entry.decompress(&mut output)?;
+            }
+        }
+        output.flush()
+    };
+    match write_files() {
+        // The reader of the output does not want more data.
+        Err(ref error) if error.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// Get the password and decrypt the archive.
+pub fn decrypt_archive(filename: &str, output: Option<String>, no_confirm: bool, password: &PasswordSource)
+    -> Result<(), Error>
+{
+    let output = output.unwrap_or_else(|| {
+        let mut path = PathBuf::from(filename);
+        path.set_extension("cbv");
+        path.into_os_string().into_string().unwrap()
+    });
+
+    if is_same_file(Path::new(filename), Path::new(&output)) {
+        return Err(usage_error(
+            format!("Cannot write the decrypted archive to {}, which is the encrypted archive", output)));
+    }
+
+    if !no_confirm {
+        ask_override_file(Path::new(&output))?;
+    }
+
+    let password = password.find(filename, io::stdout())?;
+    // NOTE: check the password before writing anything.
+    if !check_password(File::open(filename)?, &password)? {
+        return Err(ArchiveError::WrongPassword.into());
+    }
+    let mut input_file = File::open(filename)?;
+    // NOTE: the decrypted archive is only written to its path once complete.
+    let mut file = PartialFile::create(Path::new(&output))?;
+    decrypt(&mut input_file, &password, &mut file)?;
+    file.commit()
+}
+
+/// Create a CBV archive from the `inputs` files and directories.
+/// The files in a directory are archived relatively to this directory. The archive itself is never
+/// added to it.
+pub fn create(filename: &str, inputs: &[String], huffman: bool, no_confirm: bool) -> Result<(), Error> {
+    if !no_confirm {
+        ask_override_file(Path::new(filename))?;
+    }
+
+    // NOTE: the files are listed before creating the archive, so that its temporary file is not
+    // listed.
+    let excluded = Path::new(filename).canonicalize().ok();
+    let excluded = excluded.as_deref();
+    let mut files = vec![];
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            add_directory(&mut files, path, path, excluded)?;
+        }
+        else if !is_excluded(path, excluded) {
+            let name = path.file_name()
+                .and_then(OsStr::to_str)
+                .ok_or_else(|| usage_error(format!("Invalid filename {}", input)))?;
+            files.push((name.to_string(), path.to_path_buf()));
+        }
+    }
+
+    // NOTE: an existing archive is only replaced once the new one is complete.
+    let mut writer = ArchiveWriter::new(PartialFile::create(Path::new(filename))?);
+    writer.set_huffman(huffman);
+    for (name, path) in files {
+        writer.add_path(&name, &path)?;
+    }
+    writer.finish()?.commit()
+}
+
+/// Get the password and encrypt the archive into a .cbz archive.
+pub fn encrypt_archive(filename: &str, output: Option<String>, no_confirm: bool, password: &PasswordSource)
+    -> Result<(), Error>
+{
+    let output = output.unwrap_or_else(|| {
+        let mut path = PathBuf::from(filename);
+        path.set_extension("cbz");
+        path.into_os_string().into_string().unwrap()
+    });
+
+    if is_same_file(Path::new(filename), Path::new(&output)) {
+        return Err(usage_error(format!("Cannot write the encrypted archive to {}, which is the archive to encrypt",
+            output)));
+    }
+
+    // NOTE: only encrypt valid archives.
+    Archive::open(filename)?;
+
+    if !no_confirm {
+        ask_override_file(Path::new(&output))?;
+    }
+
+    // NOTE: a typo in the password would produce an archive which cannot be decrypted.
+    let password = password.read_new(io::stdout())?;
+    let input_file = File::open(filename)?;
+    // NOTE: the encrypted archive is only written to its path once complete.
+    let mut file = PartialFile::create(Path::new(&output))?;
+    encrypt(input_file, &password, &mut file)?;
+    file.commit()
+}
+
+/// Options of the extract command.
+pub struct ExtractOptions<'a> {
+    /// Encoding of the filenames.
+    pub encoding: EncodingRef,
+    /// Do not extract the files matching one of these patterns.
+    pub excludes: &'a [String],
+    /// Number of threads decompressing the files.
+    pub jobs: usize,
+    /// Also write the decrypted archive in the output directory.
+    pub keep_decrypted: bool,
+    /// What to do with the existing files.
+    pub overwrite: OverwritePolicy,
+    pub password: &'a PasswordSource,
+    /// Only extract the files matching one of these patterns (or every file if there is none).
+    pub patterns: &'a [String],
+}
+
+/// Decrypt, unarchive and decompress the files from a CBV archive.
+/// An encrypted archive is decrypted in memory, unless `keep_decrypted` is set, in which case the
+/// decrypted archive is also written in `output_dir`, following the same overwrite policy as the
+/// extracted files.
+pub fn extract(filename: &str, output_dir: &str, options: &ExtractOptions) -> Result<(), Error> {
+    let output_path = Path::new(output_dir);
+    let from_stdin = filename == STDIN_FILENAME;
+    if from_stdin && options.keep_decrypted {
+        return Err(usage_error("The decrypted archive cannot be kept when reading from stdin"));
+    }
+    // NOTE: the password of an encrypted archive is asked by decrypt_archive(), after asking to
+    // override the decrypted archive.
+    let decrypted_password =
+        if options.keep_decrypted {
+            match identify(filename)? {
+                ArchiveKind::Encrypted | ArchiveKind::PossiblyEncrypted => Some(options.password.clone()),
+                ArchiveKind::Plain | ArchiveKind::Unknown => None,
+            }
+        }
+        else {
+            None
+        };
+    // NOTE: the answers cannot be read from stdin when it contains the archive, so the existing
+    // files are not overwritten, like without any answer.
+    let mut policy =
+        if from_stdin && options.overwrite == OverwritePolicy::Ask {
+            OverwritePolicy::SkipExisting
+        }
+        else {
+            options.overwrite
+        };
+    let mut declined = 0;
+    // NOTE: a file appearing twice in the archive is considered to exist the second time.
+    let mut planned_paths = HashSet::new();
+
+    let mut decrypted_path = None;
+    if let Some(ref password) = decrypted_password {
+        let mut path = PathBuf::from(filename);
+        path.set_extension("cbv");
+        let path = output_path.join(path.file_name().unwrap());
+        let modified = metadata(filename)?.modified().ok();
+        // NOTE: the decrypted archive follows the policy of the extracted files.
+        match target_path(path, modified, &planned_paths, &mut policy)? {
+            Some(path) => {
+                let output_file = path.clone().into_os_string().into_string().unwrap();
+                decrypt_archive(filename, Some(output_file), true, password)?;
+                planned_paths.insert(path.clone());
+                decrypted_path = Some(path);
+            },
+            None if options.overwrite == OverwritePolicy::Ask => declined += 1,
+            None => (),
+        }
+    }
+    let mut archive =
+        match decrypted_path {
+            Some(path) => {
+                let file: Box<dyn Read> = Box::new(BufReader::new(File::open(path)?));
+                ArchiveReader::with_filename_encoding(file, options.encoding)?
+            },
+            // NOTE: when the decrypted archive is not written, the archive is decrypted in memory.
+            None => open_stream_archive(filename, options.encoding, options.password, io::stdout())?.0,
+        };
+
+    let mut paths = vec![None; archive.file_list().len()];
+    let mut rejected = 0;
+    let selected = select_files(archive.file_list(), options.patterns, options.excludes)?;
+    for ((file, selected), target) in archive.file_list().iter().zip(selected).zip(&mut paths) {
+        if !selected {
+            continue;
+        }
+        let path =
+            match file.output_path(output_path) {
+                Ok(path) => path,
+                Err(error) => {
+                    // NOTE: the files which would be written outside of the output directory are
+                    // skipped, and reported after extracting the other files.
+                    print_error(Format::Text, filename, &error.into());
+                    rejected += 1;
+                    continue;
+                },
+            };
+        if let Some(path) = target_path(path, file.modified, &planned_paths, &mut policy)? {
+            planned_paths.insert(path.clone());
+            *target = Some(path);
+        }
+        // NOTE: with the Ask policy, a file is only skipped because of the answer of the user.
+        else if options.overwrite == OverwritePolicy::Ask {
+            declined += 1;
+        }
+    }
+
+    archive.extract_to(&paths, options.jobs)?;
+    if rejected > 0 {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("{} {} not extracted because of an unsafe filename", rejected,
+                if rejected == 1 { "file was" } else { "files were" })));
+    }
+    if declined > 0 {
+        return Err(Error::new(ErrorKind::AlreadyExists,
+            format!("{} existing {} not overwritten", declined, if declined == 1 { "file was" } else { "files were" })));
+    }
+    Ok(())
+}
+
+/// Detect the kind of the file, recognizing a possibly encrypted archive as encrypted if one of
+/// `passwords` decrypts it, and as unknown if none does.
+fn identify_file(filename: &str, passwords: &[String]) -> Result<ArchiveKind, Error> {
+    let kind = identify(filename)?;
+    if kind != ArchiveKind::PossiblyEncrypted || passwords.is_empty() {
+        return Ok(kind);
+    }
+    for password in passwords {
+        if identify_with_password(filename, password)? == ArchiveKind::Encrypted {
+            return Ok(ArchiveKind::Encrypted);
+        }
+    }
+    Ok(ArchiveKind::Unknown)
+}
+
+/// Show whether the files are plain, (possibly) encrypted or not CBV archives.
+/// The given passwords are used to recognize the encrypted archives, but the password is never
+/// asked.
+/// Returns whether every file is recognized.
+pub fn identify_files(filenames: &[String], format: Format, password: &PasswordSource) -> Result<bool, Error> {
+    let passwords = password.given()?;
+    let mut recognized = true;
+    let results: Vec<_> = filenames.iter()
+        .map(|filename| {
+            let result = identify_file(filename, &passwords);
+            recognized &= matches!(result, Ok(ArchiveKind::Encrypted | ArchiveKind::PossiblyEncrypted |
+                ArchiveKind::Plain));
+            IdentifyRecord {
+                filename: filename.clone(),
+                kind: result.as_ref().ok().map(|&kind| kind_name(kind)),
+                error: result.err().map(|error| error.to_string()),
+            }
+        })
+        .collect();
+    print_identify(format, &results)?;
+    Ok(recognized)
+}
+
+/// Show the header fields and the total sizes of the archive.
+pub fn info(filename: &str, format: Format, encoding: EncodingRef, password: &PasswordSource) -> Result<(), Error> {
+    let (archive, encrypted) = open_stream_archive(filename, encoding, password, format.prompt_output())?;
+    let (header, file_list) = (archive.header(), archive.file_list());
+    print_info(format, &InfoRecord {
+        encrypted,
+        file_count: header.file_count(),
+        record_size: header.filename_len(),
+        compressed_size: file_list.iter().map(|file| file.compressed_size as i64).sum(),
+        decompressed_size: file_list.iter().map(|file| file.decompressed_size as i64).sum(),
+    })
+}
+
+/// Show the files of the archive.
+/// With `verbose`, the compression of their blocks is also shown, which requires to decrypt the
+/// whole archive if it is encrypted.
+pub fn list(filename: &str, verbose: bool, format: Format, encoding: EncodingRef, password: &PasswordSource)
+    -> Result<(), Error>
+{
+    let (header, files) =
+        if verbose {
+            let archive = open_archive(filename, encoding, password, format.prompt_output())?;
+            let mut files = vec![];
+            for entry in archive.entries() {
+                let flags = entry.block_flags()?;
+                files.push(FileRecord {
+                    filename: entry.filename.clone(),
+                    compressed_size: entry.compressed_size,
+                    decompressed_size: entry.decompressed_size,
+                    modified: unix_time(entry.modified),
+                    blocks: Some(BlockRecord {
+                        block_count: flags.len(),
+                        huffman_block_count: flags.iter().filter(|flags| flags.huffman_encoded).count(),
+                        lz_block_count: flags.iter().filter(|flags| flags.compressed).count(),
+                    }),
+                });
+            }
+            (archive.header().clone(), files)
+        }
+        else {
+            // NOTE: only the header and the file list are read.
+            let (archive, _) = open_stream_archive(filename, encoding, password, format.prompt_output())?;
+            let files = archive.file_list().iter()
+                .map(|file| FileRecord {
+                    filename: file.filename.clone(),
+                    compressed_size: file.compressed_size,
+                    decompressed_size: file.decompressed_size,
+                    modified: unix_time(file.modified),
+                    blocks: None,
+                })
+                .collect();
+            (archive.header().clone(), files)
+        };
+    print_list(format, &header, &files, verbose)
+}
+
+/// Check if `path` is the file `excluded`, which is canonical.
+fn is_excluded(path: &Path, excluded: Option<&Path>) -> bool {
+    excluded.is_some_and(|excluded| path.canonicalize().is_ok_and(|path| path == excluded))
+}
+
+/// Check if `path` and `other` are the same existing file.
+fn is_same_file(path: &Path, other: &Path) -> bool {
+    match (path.canonicalize(), other.canonicalize()) {
+        (Ok(path), Ok(other)) => path == other,
+        _ => false,
+    }
+}
+
+/// Get the name of the kind of archive shown by the `identify` command.
+fn kind_name(kind: ArchiveKind) -> &'static str {
+    match kind {
+        ArchiveKind::Encrypted => "encrypted",
+        ArchiveKind::Plain => "plain",
+        ArchiveKind::PossiblyEncrypted => "possibly-encrypted",
+        ArchiveKind::Unknown => "unknown",
+    }
+}
+
+/// Open the archive, decrypting it in memory if it is encrypted, and decode its filenames with
+/// `encoding`.
+/// The password prompt is shown in `prompt_output`.
+fn open_archive<W: Write>(filename: &str, encoding: EncodingRef, password: &PasswordSource, prompt_output: W)
+    -> Result<Archive, Error>
+{
+    let mut archive =
+        match archive_password(filename, password, prompt_output)? {
+            Some(password) => Archive::open_encrypted(filename, &password)?,
+            None => Archive::open(filename)?,
+        };
+    archive.set_filename_encoding(encoding)?;
+    Ok(archive)
+}
+
+/// Open the archive as a stream, decrypting it on the fly if it is encrypted, and decode its
+/// filenames with `encoding`. Also tell whether it is encrypted.
+/// The password prompt is shown in `prompt_output`.
+fn open_stream_archive<W: Write>(filename: &str, encoding: EncodingRef, password: &PasswordSource, prompt_output: W)
+    -> Result<(ArchiveReader<Box<dyn Read>>, bool), Error>
+{
+    let (input, password): (Box<dyn Read>, _) =
+        if filename == STDIN_FILENAME {
+            open_stdin(password)?
+        }
+        else {
+            let password = archive_password(filename, password, prompt_output)?;
+            (Box::new(BufReader::new(File::open(filename)?)), password)
+        };
+    let password =
+        match password {
+            Some(password) => password,
+            None => return Ok((ArchiveReader::with_filename_encoding(input, encoding)?, false)),
+        };
+
+    let input: Box<dyn Read> = Box::new(Decryptor::new(input, &password));
+    // NOTE: a decrypted header without the magic number means that the password is wrong, while the
+    // other errors come from a corrupted archive.
+    let archive = ArchiveReader::with_filename_encoding(input, encoding)
+        .map_err(|error|
+            match error {
+                ArchiveError::BadMagic => ArchiveError::WrongPassword,
+                error => error,
+            })?;
+    Ok((archive, true))
+}
+
+/// Open stdin and get the password of the archive it contains, or `None` if it is not encrypted.
+/// The beginning of stdin is read to recognize an encrypted archive, then read again by the
+/// returned reader.
+fn open_stdin(password: &PasswordSource) -> Result<(Box<dyn Read>, Option<String>), Error> {
+    let mut stdin = io::stdin();
+    let mut header = vec![];
+    stdin.by_ref().take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+    // NOTE: the input is only considered to be encrypted if the given password decrypts its
+    // header, since stdin cannot be read again to try another password, nor contain the answer of
+    // the prompt. Otherwise, it is parsed as a plain archive to report the error.
+    let password =
+        match *password {
+            PasswordSource::List(_) | PasswordSource::Prompt => None,
+            _ if header.len() < HEADER_SIZE || parse_header(&header).is_ok() => None,
+            _ => {
+                let password = password.read(io::sink())?;
+                let mut decrypted_header = [0; HEADER_SIZE];
+                Decryptor::new(&header[..], &password).read_exact(&mut decrypted_header)?;
+                parse_header(&decrypted_header).ok().map(|_| password)
+            },
+        };
+    Ok((Box::new(BufReader::new(Cursor::new(header).chain(stdin))), password))
+}
+
+/// Read the answer to a question from stdin.
+/// Without any answer, the default answer is no.
+fn read_answer() -> Answer {
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return Answer::No;
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "a" | "all" => Answer::All,
+        "none" => Answer::None,
+        answer if answer.starts_with('y') => Answer::Yes,
+        _ => Answer::No,
+    }
+}
+
+/// Parse the glob patterns.
+fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>, Error> {
+    patterns.iter()
+        .map(|pattern| Pattern::new(pattern)
+            .map_err(|error| usage_error(format!("Invalid pattern {}: {}", pattern, error))))
+        .collect()
+}
+
+/// Select the files matching one of `patterns` (or every file if there is none) and none of
+/// `excludes`, in the order of `files`.
+/// A pattern matching no file is an error.
+fn select_files(files: &[FileMetaData], patterns: &[String], excludes: &[String]) -> Result<Vec<bool>, Error> {
+    let includes = parse_patterns(patterns)?;
+    let excludes = parse_patterns(excludes)?;
+    let mut matched = vec![false; includes.len()];
+    let mut selected = vec![];
+    for file in files {
+        let mut included = includes.is_empty();
+        for (pattern, matched) in includes.iter().zip(matched.iter_mut()) {
+            if pattern.matches(&file.filename) {
+                *matched = true;
+                included = true;
+            }
+        }
+        selected.push(included && !excludes.iter().any(|pattern| pattern.matches(&file.filename)));
+    }
+
+    if let Some(index) = matched.iter().position(|&matched| !matched) {
+        return Err(usage_error(format!("No file matches the pattern {}", patterns[index])));
+    }
+    Ok(selected)
+}
+
+/// Get the path where a file modified at `modified` is written according to `policy`, or `None` to
+/// skip it.
+/// The `Ask` policy asks the user, who can change the policy for the next files.
+fn target_path(path: PathBuf, modified: Option<SystemTime>, planned_paths: &HashSet<PathBuf>,
+    policy: &mut OverwritePolicy) -> Result<Option<PathBuf>, Error>
+{
+    let exists = |path: &PathBuf| path.exists() || planned_paths.contains(path);
+    if !exists(&path) {
+        return Ok(Some(path));
+    }
+
+    let path =
+        match *policy {
+            OverwritePolicy::Ask => {
+                println!("The file {} already exists. Do you wish to override it? [y/N/all/none]", path.display());
+                match read_answer() {
+                    Answer::All => {
+                        *policy = OverwritePolicy::Overwrite;
+                        Some(path)
+                    },
+                    Answer::No => None,
+                    Answer::None => {
+                        *policy = OverwritePolicy::SkipExisting;
+                        None
+                    },
+                    Answer::Yes => Some(path),
+                }
+            },
+            // NOTE: a file which is only planned comes from a previous entry of the archive, which
+            // is overwritten like with the Overwrite policy.
+            OverwritePolicy::KeepNewer if !path.exists() => Some(path),
+            OverwritePolicy::KeepNewer => {
+                // NOTE: the times of the archive are assumed to be in UTC, like the ones written
+                // by ArchiveWriter, so they are comparable to the times of the filesystem.
+                let existing_modified = metadata(&path)?.modified().ok();
+                match (existing_modified, modified) {
+                    (Some(existing_modified), Some(modified)) if existing_modified >= modified => None,
+                    _ => Some(path),
+                }
+            },
+            OverwritePolicy::Overwrite => Some(path),
+            OverwritePolicy::Rename => {
+                let stem = path.file_stem().unwrap_or_default().to_os_string();
+                let extension = path.extension().map(OsStr::to_os_string);
+                (1 ..)
+                    .map(|index| {
+                        let mut filename = stem.clone();
+                        filename.push(format!(".{}", index));
+                        if let Some(ref extension) = extension {
+                            filename.push(".");
+                            filename.push(extension);
+                        }
+                        path.with_file_name(filename)
+                    })
+                    .find(|path| !exists(path))
+            },
+            OverwritePolicy::SkipExisting => None,
+        };
+    Ok(path)
+}
+
+/// Decode every file of the archive and check it against its meta-data, showing the result for
+/// every file.
+/// Returns whether every file is valid.
+pub fn test(filename: &str, format: Format, encoding: EncodingRef, password: &PasswordSource) -> Result<bool, Error> {
+    let archive = open_archive(filename, encoding, password, format.prompt_output())?;
+    let results: Vec<_> = archive.entries()
+        .map(|entry| {
+            let result = entry.verify();
+            TestRecord {
+                filename: entry.filename.clone(),
+                ok: result.is_ok(),
+                error: result.err().map(|error| ErrorRecord::new(&error.into())),
+            }
+        })
+        .collect();
+    print_test(format, &results)?;
+    Ok(results.iter().all(|result| result.ok))
+}
+
+/// Create the error of a command line which cannot be executed.
+pub fn usage_error<S: Into<String>>(message: S) -> Error {
+    Error::new(ErrorKind::InvalidInput, UsageError { message: message.into() })
+}
@@ -0,0 +1,355 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! CBV block compression functions.
+
+use std::cmp::{Reverse, min};
+use std::collections::BinaryHeap;
+
+/// Maximum size of the decompressed content of a block.
+pub const BLOCK_SIZE: usize = 0xF000;
+
+const HASH_SIZE: usize = 1 << 14;
+const MAX_CHAIN_LEN: usize = 64;
+const MAX_CODE_LEN: usize = 15;
+const MAX_LONG_REFERENCE_LEN: usize = 0xFF + 0x10;
+const MAX_LONG_RUN_LEN: usize = 0xFFF + 0x13;
+const MAX_OFFSET: usize = 0xFFF + 3;
+const MAX_SHORT_REFERENCE_LEN: usize = 0xF;
+const MAX_SHORT_RUN_LEN: usize = 0xF + 3;
+const MIN_MATCH_LEN: usize = 3;
+const MIN_OFFSET: usize = 3;
+const NO_POSITION: usize = usize::MAX;
+
+/// Block compression flags, as written in front of the block content.
+const FLAG_COMPRESSED: u8 = 0b01;
+const FLAG_HUFFMAN_ENCODED: u8 = 0b10;
+
+/// Writer of a stream of bits, most significant bit first.
+struct BitWriter {
+    bit_count: usize,
+    bytes: Vec<u8>,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bit_count: 0,
+            bytes: vec![],
+        }
+    }
+
+    /// Write the `count` least significant bits of `bits`.
+    fn write(&mut self, bits: u16, count: usize) {
+        for index in (0 .. count).rev() {
+            if self.bit_count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (bits >> index) & 1 == 1 {
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= 0x80 >> (self.bit_count % 8);
+            }
+            self.bit_count += 1;
+        }
+    }
+}
+
+/// Writer of the compressed items, grouped by 16 behind a control word telling which items are
+/// coded.
+struct ItemWriter {
+    control: u16,
+    control_position: usize,
+    item_count: usize,
+    output: Vec<u8>,
+}
+
+impl ItemWriter {
+    fn new(capacity: usize) -> ItemWriter {
+        ItemWriter {
+            control: 0,
+            control_position: 0,
+            item_count: 0,
+            output: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Write an item, which is a literal byte if not `coded`.
+    fn write(&mut self, coded: bool, bytes: &[u8]) {
+        if self.item_count == 0 {
+            self.control_position = self.output.len();
+            self.output.extend_from_slice(&[0, 0]);
+        }
+        self.control <<= 1;
+        if coded {
+            self.control |= 1;
+        }
+        self.output.extend_from_slice(bytes);
+        self.item_count += 1;
+        if self.item_count == 16 {
+            self.flush_control();
+        }
+    }
+
+    fn flush_control(&mut self) {
+        let control = self.control << (16 - self.item_count);
+        self.output[self.control_position] = control as u8;
+        self.output[self.control_position + 1] = (control >> 8) as u8;
+        self.control = 0;
+        self.item_count = 0;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.item_count > 0 {
+            self.flush_control();
+        }
+        self.output
+    }
+}
+
+/// Compute the checksum of a block: the sum of its little-endian 16-bit words.
+/// The last byte of a block of odd size is not part of the checksum.
+pub fn checksum(block: &[u8]) -> u16 {
+    block.chunks(2)
+        .filter(|word| word.len() == 2)
+        .fold(0u16, |sum, word| sum.wrapping_add(word[0] as u16 | (word[1] as u16) << 8))
+}
+
+/// Compress `input` using run-length encoding and backward references, in the format read by
+/// `decompress_block`.
+pub fn compress_block(input: &[u8]) -> Vec<u8> {
+    let mut writer = ItemWriter::new(input.len() + input.len() / 8 + 2);
+    let mut heads = vec![NO_POSITION; HASH_SIZE];
+    let mut previous = vec![NO_POSITION; input.len()];
+    let mut position = 0;
+
+    while position < input.len() {
+        let run_len = run_len(input, position);
+        let (offset, reference_len) = find_reference(input, position, &heads, &previous);
+
+        let size =
+            if run_len >= MIN_MATCH_LEN && run_len >= reference_len {
+                if run_len <= MAX_SHORT_RUN_LEN {
+                    writer.write(true, &[(run_len - 3) as u8, input[position]]);
+                }
+                else {
+                    let size = run_len - 0x13;
+                    writer.write(true, &[0x10 | (size & 0xF) as u8, (size >> 4) as u8, input[position]]);
+                }
+                run_len
+            }
+            else if reference_len >= MIN_MATCH_LEN {
+                let offset = offset - 3;
+                let low = (offset & 0xF) as u8;
+                let high = (offset >> 4) as u8;
+                if reference_len <= MAX_SHORT_REFERENCE_LEN {
+                    writer.write(true, &[(reference_len as u8) << 4 | low, high]);
+                }
+                else {
+                    writer.write(true, &[0x20 | low, high, (reference_len - 0x10) as u8]);
+                }
+                reference_len
+            }
+            else {
+                writer.write(false, &[input[position]]);
+                1
+            };
+
+        for index in position .. position + size {
+            if index + MIN_MATCH_LEN <= input.len() {
+                let hash = hash(&input[index..]);
+                previous[index] = heads[hash];
+                heads[hash] = index;
+            }
+        }
+        position += size;
+    }
+
+    writer.finish()
+}
+
+/// Encode a chunk of a file as a block (with its size and checksum), choosing the smallest
+/// representation.
+pub fn encode_block(input: &[u8], huffman: bool) -> Vec<u8> {
+    let compressed = compress_block(input);
+    let mut flag = 0;
+    let mut content = input.to_vec();
+
+    if huffman {
+        if let Some(encoded) = huffman_encode(input) {
+            if encoded.len() < content.len() {
+                flag = FLAG_HUFFMAN_ENCODED;
+                content = encoded;
+            }
+        }
+        if let Some(encoded) = huffman_encode(&compressed) {
+            if encoded.len() < content.len() {
+                flag = FLAG_COMPRESSED | FLAG_HUFFMAN_ENCODED;
+                content = encoded;
+            }
+        }
+    }
+    if compressed.len() < content.len() {
+        flag = FLAG_COMPRESSED;
+        content = compressed;
+    }
+
+    let mut payload = Vec::with_capacity(content.len() + 1);
+    payload.push(flag);
+    payload.extend_from_slice(&content);
+
+    let size = payload.len() as u16;
+    let checksum = checksum(&payload);
+    let mut block = Vec::with_capacity(payload.len() + 4);
+    block.extend_from_slice(&[size as u8, (size >> 8) as u8, checksum as u8, (checksum >> 8) as u8]);
+    block.extend_from_slice(&payload);
+    block
+}
+
+/// Find the longest content already seen which is equal to the content at `position`.
+/// Returns the offset and the length of the backward reference.
+fn find_reference(input: &[u8], position: usize, heads: &[usize], previous: &[usize]) -> (usize, usize) {
+    let mut best = (0, 0);
+    if position + MIN_MATCH_LEN > input.len() {
+        return best;
+    }
+
+    let mut candidate = heads[hash(&input[position..])];
+    let mut chain_len = 0;
+    while candidate != NO_POSITION && chain_len < MAX_CHAIN_LEN {
+        let offset = position - candidate;
+        if offset > MAX_OFFSET {
+            break;
+        }
+        if offset >= MIN_OFFSET {
+            // NOTE: the referenced content can overlap the content at `position`.
+            let max_len = min(MAX_LONG_REFERENCE_LEN, input.len() - position);
+            let len = input[candidate..].iter()
+                .zip(&input[position .. position + max_len])
+                .take_while(|&(byte1, byte2)| byte1 == byte2)
+                .count();
+            if len > best.1 {
+                best = (offset, len);
+                if len == max_len {
+                    break;
+                }
+            }
+        }
+        candidate = previous[candidate];
+        chain_len += 1;
+    }
+    best
+}
+
+/// Hash the next 3 bytes.
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) % HASH_SIZE
+}
+
+/// Compute the length of the Huffman code of every byte value, limited to `MAX_CODE_LEN` bits.
+fn huffman_code_lengths(input: &[u8]) -> [usize; 256] {
+    let mut frequencies = [0usize; 256];
+    for &byte in input {
+        frequencies[byte as usize] += 1;
+    }
+
+    loop {
+        let mut lengths = [0; 256];
+        let mut heap = BinaryHeap::new();
+        // Every node is represented by the symbols it contains.
+        let mut nodes: Vec<Vec<usize>> = vec![];
+        for (symbol, &frequency) in frequencies.iter().enumerate() {
+            if frequency > 0 {
+                heap.push(Reverse((frequency, nodes.len())));
+                nodes.push(vec![symbol]);
+            }
+        }
+
+        if nodes.len() == 1 {
+            lengths[nodes[0][0]] = 1;
+            return lengths;
+        }
+
+        while heap.len() > 1 {
+            let Reverse((frequency1, node1)) = heap.pop().unwrap();
+            let Reverse((frequency2, node2)) = heap.pop().unwrap();
+            let mut symbols = vec![];
+            for &node in &[node1, node2] {
+                for &symbol in &nodes[node] {
+                    lengths[symbol] += 1;
+                }
+                symbols.extend_from_slice(&nodes[node]);
+            }
+            heap.push(Reverse((frequency1 + frequency2, nodes.len())));
+            nodes.push(symbols);
+        }
+
+        if lengths.iter().all(|&length| length <= MAX_CODE_LEN) {
+            return lengths;
+        }
+
+        // Flatten the frequencies until the codes are short enough.
+        for frequency in frequencies.iter_mut() {
+            if *frequency > 0 {
+                *frequency = frequency.div_ceil(2);
+            }
+        }
+    }
+}
+
+/// Encode `input` using a Huffman code, prefixed by the size of `input` and the code of every
+/// byte value, in the format read by the `huffman` parser.
+/// Returns None if `input` is too big to be Huffman-encoded.
+pub fn huffman_encode(input: &[u8]) -> Option<Vec<u8>> {
+    if input.is_empty() || input.len() > u16::MAX as usize {
+        return None;
+    }
+
+    let lengths = huffman_code_lengths(input);
+
+    // Assign the canonical codes.
+    let mut symbols: Vec<usize> = (0 .. 256).filter(|&symbol| lengths[symbol] > 0).collect();
+    symbols.sort_by_key(|&symbol| (lengths[symbol], symbol));
+    let mut codes = [0u16; 256];
+    let mut code = 0u16;
+    let mut previous_length = lengths[symbols[0]];
+    for &symbol in &symbols {
+        code <<= lengths[symbol] - previous_length;
+        codes[symbol] = code;
+        code = code.wrapping_add(1);
+        previous_length = lengths[symbol];
+    }
+
+    let mut writer = BitWriter::new();
+    writer.write(input.len() as u16, 16);
+    for symbol in 0 .. 256 {
+        writer.write(lengths[symbol] as u16, 4);
+        writer.write(codes[symbol], lengths[symbol]);
+    }
+    for &byte in input {
+        writer.write(codes[byte as usize], lengths[byte as usize]);
+    }
+    Some(writer.bytes)
+}
+
+/// Count how many times the byte at `position` is repeated.
+fn run_len(input: &[u8], position: usize) -> usize {
+    let byte = input[position];
+    input[position .. min(input.len(), position + MAX_LONG_RUN_LEN)].iter()
+        .take_while(|&&current| current == byte)
+        .count()
+}
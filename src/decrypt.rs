@@ -15,67 +15,110 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-//! CBV decryption functions.
+//! CBV encryption and decryption functions.
 
-use std::io::{self, BufReader, Read, Write};
+use std::cmp::min;
+use std::io::{self, Read, Write};
 
 use des;
 
 const BUFFER_SIZE: usize = 8 * 10000;
+pub const DES_BLOCK_SIZE: usize = 8;
 const PASSWORD_LEN: usize = 8;
 
-/// Create the decryption key from the password.
+/// Create the DES key from the password.
 fn create_key(password: &str) -> [u8; PASSWORD_LEN] {
-    if password.len() < PASSWORD_LEN {
-        // If the password length is lesser than 8, the password is repeated until its len is 8.
-        let mut new_password = password.to_string();
-        while new_password.len() < PASSWORD_LEN {
-            new_password.push_str(password);
-        }
-        copy_into_array(new_password.as_bytes())
-    }
-    else if password.len() > PASSWORD_LEN {
+    let bytes = password.as_bytes();
+    if bytes.len() > PASSWORD_LEN {
         // If the password length is greater than 8, the password is hashed.
-        let mut key = [0; PASSWORD_LEN];
-        let bytes = password.as_bytes();
+        let mut key = [0u8; PASSWORD_LEN];
 
         for (i, &byte) in bytes.iter().enumerate() {
             let index = i % 8;
-            key[index] *= 2;
-            key[index] ^= byte;
+            key[index] = key[index].wrapping_mul(2) ^ byte;
         }
 
         key
     }
     else {
-        copy_into_array(password.as_bytes())
+        // If the password length is lesser than 8, the password is repeated until its len is 8.
+        copy_into_array(bytes.iter().cycle())
     }
 }
 
-/// Copy a slice into an array.
-fn copy_into_array(slice: &[u8]) -> [u8; PASSWORD_LEN] {
+/// Copy the first bytes of an iterator into an array.
+fn copy_into_array<'a, I: Iterator<Item=&'a u8>>(bytes: I) -> [u8; PASSWORD_LEN] {
     let mut array = [0; PASSWORD_LEN];
-    for (index, &byte) in slice.iter().enumerate() {
-        array[index] = byte;
+    for (element, &byte) in array.iter_mut().zip(bytes) {
+        *element = byte;
     }
     array
 }
 
+/// Reader decrypting an encrypted archive as it is read.
+pub struct Decryptor<R> {
+    buffer: Vec<u8>,
+    key: [u8; PASSWORD_LEN],
+    position: usize,
+    reader: R,
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Create a reader decrypting the content of `reader` using `password`.
+    pub fn new(reader: R, password: &str) -> Decryptor<R> {
+        Decryptor {
+            buffer: vec![],
+            key: create_key(password),
+            position: 0,
+            reader,
+        }
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        if self.position == self.buffer.len() {
+            let mut encrypted = Vec::with_capacity(BUFFER_SIZE);
+            let byte_count = self.reader.by_ref().take(BUFFER_SIZE as u64).read_to_end(&mut encrypted)?;
+            if byte_count == 0 {
+                return Ok(0);
+            }
+
+            encrypted.resize(byte_count.next_multiple_of(DES_BLOCK_SIZE), 0);
+            self.buffer = des::decrypt(&encrypted, &self.key);
+            self.buffer.truncate(byte_count);
+            self.position = 0;
+        }
+
+        let size = min(buffer.len(), self.buffer.len() - self.position);
+        buffer[..size].copy_from_slice(&self.buffer[self.position .. self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
 /// Decrypt the file into `output`.
-pub fn decrypt<R: Read>(reader: R, password: &str, output: &mut Write) -> Result<(), io::Error> {
+pub fn decrypt<R: Read>(reader: R, password: &str, output: &mut dyn Write) -> Result<(), io::Error> {
+    io::copy(&mut Decryptor::new(reader, password), output)?;
+    Ok(())
+}
+
+/// Encrypt the file into `output`.
+/// The end of the file is padded with zeros to fill the last DES block.
+pub fn encrypt<R: Read>(mut reader: R, password: &str, output: &mut dyn Write) -> Result<(), io::Error> {
     let key = create_key(password);
 
-    let mut reader = BufReader::new(reader);
-    let mut buffer = [0; BUFFER_SIZE];
+    let mut buffer = Vec::with_capacity(BUFFER_SIZE);
 
-    while let Ok(byte_count) = reader.read(&mut buffer) {
+    loop {
+        buffer.clear();
+        let byte_count = reader.by_ref().take(BUFFER_SIZE as u64).read_to_end(&mut buffer)?;
         if byte_count == 0 {
             break;
         }
 
-        let result = des::decrypt(&buffer, &key);
-
-        try!(output.write_all(&result[..byte_count]));
+        buffer.resize(byte_count.next_multiple_of(DES_BLOCK_SIZE), 0);
+        output.write_all(&des::encrypt(&buffer, &key))?;
     }
 
     Ok(())
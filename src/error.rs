@@ -0,0 +1,118 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Errors of the archive reader.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+
+/// Error which occurred while reading an archive.
+/// The offsets are the positions in the (decrypted) archive, in bytes.
+#[derive(Debug)]
+pub enum Error {
+    /// The block has an unknown compression flag or refers to missing bytes.
+    BadBlock { entry: String, block_index: usize, offset: usize },
+    /// The checksum of the block does not match its content.
+    BadChecksum { entry: String, block_index: usize, offset: usize },
+    /// The file records are too small to contain a filename and the sizes.
+    BadFileList,
+    /// The file does not start with the CBV magic number.
+    BadMagic,
+    /// The size of the compressed content does not match the file list.
+    CompressedSize { entry: String, actual: usize, expected: i32 },
+    /// The size of the decompressed content does not match the file list.
+    DecompressedSize { entry: String, actual: usize, expected: i32 },
+    /// The Huffman tree of the block is truncated or the block contains a code which is not in it.
+    HuffmanTree { entry: String, block_index: usize, offset: usize },
+    /// Error from the underlying file or reader.
+    Io(io::Error),
+    /// The block extends past the end of the entry.
+    TruncatedBlock { entry: String, block_index: usize, offset: usize },
+    /// The archive ends before the end of the file list.
+    TruncatedFileList,
+    /// The archive is smaller than its header.
+    TruncatedHeader,
+    /// The filename would escape the output directory.
+    UnsafeFilename { filename: String, reason: &'static str },
+    /// The decrypted archive is invalid, because the password is wrong or the file is not an
+    /// encrypted archive.
+    WrongPassword,
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::BadBlock { ref entry, block_index, offset } =>
+                write!(formatter, "Bad block {} of {} at offset {}", block_index, entry, offset),
+            Error::BadChecksum { ref entry, block_index, offset } =>
+                write!(formatter, "Bad checksum in block {} of {} at offset {}", block_index, entry, offset),
+            Error::BadFileList => write!(formatter, "Bad file list"),
+            Error::BadMagic => write!(formatter, "Not a CBV archive"),
+            Error::CompressedSize { ref entry, actual, expected } =>
+                write!(formatter, "The compressed size of {} is {} bytes instead of {}", entry, actual, expected),
+            Error::DecompressedSize { ref entry, actual, expected } =>
+                write!(formatter, "The decompressed size of {} is {} bytes instead of {}", entry, actual, expected),
+            Error::HuffmanTree { ref entry, block_index, offset } =>
+                write!(formatter, "Bad Huffman tree or code in block {} of {} at offset {}", block_index, entry, offset),
+            Error::Io(ref error) => error.fmt(formatter),
+            Error::TruncatedBlock { ref entry, block_index, offset } =>
+                write!(formatter, "Truncated block {} of {} at offset {}", block_index, entry, offset),
+            Error::TruncatedFileList => write!(formatter, "Truncated file list"),
+            Error::TruncatedHeader => write!(formatter, "Truncated archive header"),
+            Error::UnsafeFilename { ref filename, reason } =>
+                write!(formatter, "Unsafe filename {:?}: {}", filename, reason),
+            Error::WrongPassword => write!(formatter, "Wrong password, or not an encrypted CBV archive"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        // NOTE: an EntryReader wraps its errors in an io::Error.
+        if error.get_ref().is_none_or(|inner| !inner.is::<Error>()) {
+            return Error::Io(error);
+        }
+        let kind = error.kind();
+        match error.into_inner().map(|inner| inner.downcast::<Error>()) {
+            Some(Ok(error)) => *error,
+            Some(Err(inner)) => Error::Io(io::Error::new(kind, inner)),
+            None => Error::Io(kind.into()),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        let kind =
+            match error {
+                Error::Io(error) => return error,
+                Error::WrongPassword => ErrorKind::InvalidInput,
+                _ => ErrorKind::InvalidData,
+            };
+        io::Error::new(kind, error)
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Library to read and write CBV archives.
+//!
+//! # Usage
+//!
+//! Open an `Archive` and iterate over its entries to list or decompress the files:
+//!
+//! ```no_run
+//! use uncbv::Archive;
+//!
+//! let archive = Archive::open("archive.cbv").unwrap();
+//! for entry in archive.entries() {
+//!     println!("{} ({} bytes)", entry.filename, entry.decompressed_size);
+//! }
+//! archive.extract("output").unwrap();
+//! ```
+//!
+//! A single entry can also be read without writing it to the disk, and without decoding the
+//! other entries:
+//!
+//! ```no_run
+//! use std::io::Read;
+//!
+//! use uncbv::Archive;
+//!
+//! let archive = Archive::open("archive.cbv").unwrap();
+//! let entry = archive.entry("database.cbh").unwrap();
+//! let mut content = vec![];
+//! entry.reader().read_to_end(&mut content).unwrap();
+//! ```
+//!
+//! Create an archive with an `ArchiveWriter`:
+//!
+//! ```no_run
+//! use std::fs::File;
+//!
+//! use uncbv::ArchiveWriter;
+//!
+//! let mut writer = ArchiveWriter::new(File::create("archive.cbv").unwrap());
+//! writer.add_path("database.cbh", "database/database.cbh").unwrap();
+//! writer.finish().unwrap();
+//! ```
+
+/*
+ * TODO: use bits! and take_bits!(4) to parse high and low.
+ * TODO: try to use length_value!, length_count! or length_bytes! macros.
+ * TODO: Use 2 levels of parser. The second level receive a Write parameter.
+ * TODO: Create macros to keep a state within a parser (or use a struct with parser methods?).
+ * TODO: Use Vec::with_capacity() in the crates des, huffman and uncbv.
+ * TODO: Add a state representing the result in the decompressor: (&[u8], Vec<u8>) instead of &[u8].
+ * TODO: Decompress as a vector of Byte | RunLength | BackwardReference (perhaps there is a better
+ * way).
+ */
+
+// NOTE: the nom macros do not keep the doc comments of the parsers.
+#![allow(unused_doc_comments)]
+
+extern crate des;
+extern crate encoding;
+extern crate huffman;
+extern crate memmap;
+#[macro_use]
+extern crate nom;
+
+#[macro_use]
+mod macros;
+mod archive;
+mod cbv;
+mod codepage;
+mod compress;
+mod decrypt;
+mod error;
+mod partial;
+mod pool;
+mod stream;
+mod writer;
+
+pub use archive::{Archive, ArchiveKind, Entries, Entry, EntryReader, check_password, extract_entries,
+    extract_entries_to, identify, identify_with_password, parse_file_list, parse_file_list_with_encoding, parse_header};
+pub use cbv::{CompressionFlags, FileMetaData, HEADER_SIZE, Header};
+pub use codepage::filename_encoding;
+pub use compress::checksum;
+pub use decrypt::{Decryptor, decrypt, encrypt};
+pub use encoding::EncodingRef;
+pub use error::Error;
+pub use partial::{PartialFile, remove_partial_files};
+pub use stream::{ArchiveReader, StreamEntry};
+pub use writer::ArchiveWriter;
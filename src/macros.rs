@@ -1,25 +1,3 @@
-/// Apply a parser over every element of an iterable.
-macro_rules! foreach {
-    ($input:expr, $iterable:expr, $element:ident => $submac:ident!( $($args:tt)* )) => {{
-        let mut input = $input;
-        for $element in $iterable {
-            match $submac!(input, $($args)*) {
-                $crate::nom::IResult::Done(new_input, _) => {
-                    input = new_input;
-                },
-                $crate::nom::IResult::Error(_) => {
-                    return $crate::nom::IResult::Error(error_position!($crate::nom::ErrorKind::Many0, $input));
-                },
-                $crate::nom::IResult::Incomplete(_) => {
-                    return $crate::nom::IResult::Incomplete($crate::nom::Needed::Unknown);
-                },
-            }
-        }
-
-        $crate::nom::IResult::Done(input, ())
-    }};
-}
-
 /// Makes a function from a parser combination with arguments.
 macro_rules! named_args {
     (pub $func_name:ident ( $( $arg:ident : $typ:ty ),* ) < $return_type:ty > , $submac:ident!( $($args:tt)* ) ) => {
@@ -17,66 +17,90 @@
 
 /*
  * TODO: switch to clap.
- * TODO: show the compressed and decompressed size in the list command.
- * TODO: might be quicker by not opening the file at every block.
- * TODO: use bits! and take_bits!(4) to parse high and low.
- * TODO: replace chain! macros by do_parse!.
- * TODO: try to use length_value!, length_count! or length_bytes! macros.
- * TODO: Use 2 levels of parser. The second level receive a Write parameter.
- * TODO: Create macros to keep a state within a parser (or use a struct with parser methods?).
  * TODO: Use Buf{Reader|Writer} whenever possible.
- * TODO: Use Vec::with_capacity() in the crates des, huffman and uncbv.
- * TODO: This software consumes too much memory (because of mmaping the file).
- * TODO: Better error handling.
- * TODO: Decompress the files concurrently.
- * TODO: Add a state representing the result in the decompressor: (&[u8], Vec<u8>) instead of &[u8].
- * TODO: Write in a channel instead of in the files directly (perhaps there is a better way).
- * TODO: Decompress as a vector of Byte | RunLength | BackwardReference (perhaps there is a better
- * way).
- * TODO: Create a crate for the cbv parser (Add the coverage badge and update the travis script to
- * use coveralls (since travis-cargo does not work)).
+ * TODO: Add the coverage badge and update the CI script to use coveralls.
  */
 
-extern crate des;
+extern crate ctrlc;
 extern crate docopt;
-extern crate encoding;
-extern crate huffman;
-extern crate memmap;
-#[macro_use]
-extern crate nom;
+extern crate glob;
+extern crate rpassword;
 extern crate serde;
+extern crate serde_json;
+extern crate uncbv;
 
-mod archive;
-mod decrypt;
-#[macro_use]
-mod macros;
-mod cbv;
+mod commands;
+mod output;
+mod password;
 
+use std::io::{Error, ErrorKind};
 use std::path::Path;
+use std::process;
+use std::thread;
 
 use docopt::Docopt;
 use docopt::Error::{Argv, WithProgramUsage};
 use serde::Deserialize;
+use uncbv::{filename_encoding, remove_partial_files};
 
-use archive::{decrypt_archive, extract, get_file_list};
+use commands::{ExtractOptions, OverwritePolicy, UsageError, cat, create, decrypt_archive, encrypt_archive, extract,
+    identify_files, info, list, test};
+use output::{Format, print_error};
+use password::{NoValidPassword, PasswordSource};
 
-const PKG_NAME: &'static str = "uncbv";
+/// Exit code when the archive is corrupted or is not a CBV archive.
+const CORRUPT_ARCHIVE_EXIT_CODE: i32 = 4;
+/// Exit code when the user does not want to override an existing file.
+const DECLINED_OVERWRITE_EXIT_CODE: i32 = 6;
+/// Exit code of the failures which have no specific exit code, like an unknown file for identify.
+const FAILURE_EXIT_CODE: i32 = 1;
+/// Exit code of a program interrupted by SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+/// Exit code when a file cannot be read or written.
+const IO_ERROR_EXIT_CODE: i32 = 3;
+const PKG_NAME: &str = "uncbv";
+/// Exit code of an invalid command line.
+const USAGE_EXIT_CODE: i32 = 2;
+/// Exit code when the password of the encrypted archive is wrong.
+const WRONG_PASSWORD_EXIT_CODE: i32 = 5;
 
-const USAGE: &'static str = "
-CBV unarchiver.
+const USAGE: &str = "
+CBV archive utility.
 
 Usage:
-    uncbv (l | list) <filename>
-    uncbv (x | extract) <filename> [(--output=<output> | --create-dir)] [--no-confirm]
-    uncbv (d | decrypt) <filename> [--output=<output>] [--no-confirm]
+    uncbv (l | list) <filename> [--verbose] [--format=<format>] [--filename-encoding=<encoding>] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv (x | extract) <filename> [<pattern>...] [--exclude=<pattern>...] [(--output=<output> | --create-dir | --pipe)] [--keep-decrypted] [--jobs=<jobs>] [(--no-confirm | --overwrite | --skip-existing | --keep-newer | --rename)] [--filename-encoding=<encoding>] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv (t | test) <filename> [--format=<format>] [--filename-encoding=<encoding>] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv identify <input>... [--format=<format>] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv info <filename> [--format=<format>] [--filename-encoding=<encoding>] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv cat <filename> [<pattern>...] [--exclude=<pattern>...] [--filename-encoding=<encoding>] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv (d | decrypt) <filename> [--output=<output>] [--no-confirm] [(--password=<password> | --password-file=<file> | --password-fd=<fd> | --password-list=<file>)]
+    uncbv (e | encrypt) <filename> [--output=<output>] [--no-confirm] [(--password=<password> | --password-file=<file> | --password-fd=<fd>)]
+    uncbv (c | create) <filename> <input>... [--no-huffman] [--no-confirm]
     uncbv (-h | --help)
     uncbv (-V | --version)
 
 Options:
     -c --create-dir         Extract in a new directory (uncbv extract <filename>.cbv -c is equivalent to uncbv extract <filename>.cbv -o <filename>).
+    --exclude <pattern>     Do not extract the files matching this glob pattern.
+    --filename-encoding <encoding>  Code page of the filenames in the archive, like cp1251, cp1250 or cp437 [default: cp1252].
+    -f --format <format>    Output format of list, test, identify and info: text, json or csv [default: text].
     -h --help               Show this help.
+    -j --jobs <jobs>        Number of threads used to decompress the files (at most four per CPU), 0 to use one per CPU [default: 1].
+    --keep-decrypted        Also write the decrypted archive (.cbv) when extracting an encrypted archive.
+    --keep-newer            Do not override the existing files which are not older than the files in the archive (whose times are assumed to be in UTC).
     --no-confirm            Do not ask for any confirmation before overriding.
-    -o --output <output>    Set output directory.
+    --overwrite             Override the existing files without asking (same as --no-confirm).
+    --no-huffman            Do not use Huffman coding in the created archive.
+    --password <password>   Password of the encrypted archive (the UNCBV_PASSWORD environment variable can also be used).
+    --password-fd <fd>      Read the password from the first line of this file descriptor.
+    --password-file <file>  Read the password from the first line of this file.
+    --password-list <file>  Try every line of this file as the password of the encrypted archive.
+    -p --pipe               Write the content of the files to stdout instead of extracting them.
+    -o --output <output>    Set output directory (or output file for decrypt and encrypt).
+    --rename                Extract the files which already exist under a new name (file.1.ext).
+    --skip-existing         Do not extract the files which already exist.
+    -v --verbose            Show the sizes and the compression of the files.
     -V --version            Show the version of uncbv.
 ";
 
@@ -91,71 +115,218 @@ macro_rules! cmd_match {
     };
 }
 
-/// Unwrap the result or show the error and return from the function.
+/// Unwrap the result or show the error (in the specified format) and exit with the exit code
+/// corresponding to the error.
 macro_rules! parse_or_show_error {
-    ($parser:expr, $filename:expr $(, $args:expr )*) => {
+    ($format:expr; $parser:expr, $filename:expr $(, $args:expr )*) => {
         match $parser($filename $(, $args )*) {
             Ok(result) => {
                 result
             },
             Err(error) => {
-                println!("{}: {}", $filename, error);
-                return;
+                print_error($format, $filename, &error);
+                process::exit(exit_code(&error));
             },
         }
     };
+    ($parser:expr, $filename:expr $(, $args:expr )*) => {
+        parse_or_show_error!(Format::Text; $parser, $filename $(, $args )*)
+    };
 }
 
 #[derive(Debug, Deserialize)]
 struct Args {
     arg_filename: String,
+    arg_input: Vec<String>,
+    arg_pattern: Vec<String>,
     flag_create_dir: bool,
+    flag_exclude: Vec<String>,
+    flag_filename_encoding: String,
+    flag_format: Format,
+    flag_jobs: usize,
+    flag_keep_decrypted: bool,
+    flag_keep_newer: bool,
     flag_no_confirm: bool,
+    flag_no_huffman: bool,
     flag_output: Option<String>,
+    flag_overwrite: bool,
+    flag_password: Option<String>,
+    flag_password_fd: Option<i32>,
+    flag_password_file: Option<String>,
+    flag_password_list: Option<String>,
+    flag_pipe: bool,
+    flag_rename: bool,
+    flag_skip_existing: bool,
+    flag_verbose: bool,
+    cmd_c: bool,
+    cmd_cat: bool,
+    cmd_create: bool,
     cmd_d: bool,
     cmd_decrypt: bool,
+    cmd_e: bool,
+    cmd_encrypt: bool,
     cmd_extract: bool,
+    cmd_identify: bool,
+    cmd_info: bool,
     cmd_l: bool,
     cmd_list: bool,
+    cmd_t: bool,
+    cmd_test: bool,
     cmd_x: bool,
 }
 
 fn main() {
     let args = valid_args();
 
+    // NOTE: do not leave partially extracted files when interrupted.
+    let _ = ctrlc::set_handler(|| {
+        remove_partial_files();
+        process::exit(INTERRUPTED_EXIT_CODE);
+    });
+
     let filename = &args.arg_filename;
+    // NOTE: the encoding is checked by valid_args().
+    let encoding = filename_encoding(&args.flag_filename_encoding).unwrap();
+    let password = PasswordSource::new(args.flag_password.clone(), args.flag_password_file.clone(),
+        args.flag_password_fd, args.flag_password_list.clone());
 
     cmd_match!(args {
         cmd_list | cmd_l => {
-            let files = parse_or_show_error!(get_file_list, filename);
-            for file in files {
-                println!("{}", file.filename);
+            parse_or_show_error!(args.flag_format; list, filename, args.flag_verbose, args.flag_format, encoding,
+                &password);
+        },
+
+        cmd_identify => {
+            // NOTE: the errors of the files are shown with their kind, so the only errors left are
+            // the ones reading the passwords and writing the output.
+            let recognized = identify_files(&args.arg_input, args.flag_format, &password).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(exit_code(&error));
+            });
+            if !recognized {
+                process::exit(FAILURE_EXIT_CODE);
             }
         },
 
+        cmd_info => {
+            parse_or_show_error!(args.flag_format; info, filename, args.flag_format, encoding, &password);
+        },
+
+        cmd_cat => {
+            parse_or_show_error!(cat, filename, &args.arg_pattern, &args.flag_exclude, encoding, &password);
+        },
+
         cmd_extract | cmd_x => {
+            if args.flag_pipe {
+                parse_or_show_error!(cat, filename, &args.arg_pattern, &args.flag_exclude, encoding, &password);
+                return;
+            }
+
             let output =
                 if args.flag_create_dir {
                     let path = Path::new(filename);
                     path.file_stem().unwrap().to_str().unwrap().to_string()
                 }
                 else {
-                    args.flag_output.unwrap_or_else(|| ".".to_string())
+                    args.flag_output.clone().unwrap_or_else(|| ".".to_string())
                 };
-            parse_or_show_error!(extract, filename, &output, args.flag_no_confirm);
+            let jobs =
+                if args.flag_jobs == 0 {
+                    thread::available_parallelism().map(|jobs| jobs.get()).unwrap_or(1)
+                }
+                else {
+                    args.flag_jobs
+                };
+            let options = ExtractOptions {
+                encoding,
+                excludes: &args.flag_exclude,
+                jobs,
+                keep_decrypted: args.flag_keep_decrypted,
+                overwrite: overwrite_policy(&args),
+                password: &password,
+                patterns: &args.arg_pattern,
+            };
+            parse_or_show_error!(extract, filename, &output, &options);
+        },
+
+        cmd_test | cmd_t => {
+            if !parse_or_show_error!(args.flag_format; test, filename, args.flag_format, encoding, &password) {
+                process::exit(CORRUPT_ARCHIVE_EXIT_CODE);
+            }
         },
 
         cmd_decrypt | cmd_d => {
-            decrypt_archive(filename, args.flag_output, args.flag_no_confirm);
+            parse_or_show_error!(decrypt_archive, filename, args.flag_output, args.flag_no_confirm, &password);
+        },
+
+        cmd_encrypt | cmd_e => {
+            parse_or_show_error!(encrypt_archive, filename, args.flag_output, args.flag_no_confirm, &password);
+        },
+
+        cmd_create | cmd_c => {
+            parse_or_show_error!(create, filename, &args.arg_input, !args.flag_no_huffman, args.flag_no_confirm);
         },
     });
 }
 
+/// Get the exit code corresponding to `error`.
+fn exit_code(error: &Error) -> i32 {
+    if let Some(error) = error.get_ref().and_then(|error| error.downcast_ref::<uncbv::Error>()) {
+        return
+            match *error {
+                uncbv::Error::Io(ref error) => exit_code(error),
+                uncbv::Error::WrongPassword => WRONG_PASSWORD_EXIT_CODE,
+                _ => CORRUPT_ARCHIVE_EXIT_CODE,
+            };
+    }
+    if error.get_ref().is_some_and(|error| error.is::<NoValidPassword>()) {
+        return WRONG_PASSWORD_EXIT_CODE;
+    }
+    if error.get_ref().is_some_and(|error| error.is::<UsageError>()) {
+        return USAGE_EXIT_CODE;
+    }
+    match error.kind() {
+        ErrorKind::AlreadyExists => DECLINED_OVERWRITE_EXIT_CODE,
+        ErrorKind::Interrupted => INTERRUPTED_EXIT_CODE,
+        ErrorKind::InvalidData => CORRUPT_ARCHIVE_EXIT_CODE,
+        _ => IO_ERROR_EXIT_CODE,
+    }
+}
+
+/// Show the command-line error and exit.
+/// Help and version are shown on stdout with a success exit code.
+fn exit_with_usage(error: docopt::Error) -> ! {
+    if error.fatal() {
+        eprintln!("{}", error);
+        process::exit(USAGE_EXIT_CODE);
+    }
+    error.exit()
+}
+
 /// Check if the command is extract.
 fn is_extract_command(args: &Args) -> bool {
     args.cmd_extract || args.cmd_x
 }
 
+/// Get the policy for the existing files from the arguments of the extract command.
+fn overwrite_policy(args: &Args) -> OverwritePolicy {
+    if args.flag_no_confirm || args.flag_overwrite {
+        OverwritePolicy::Overwrite
+    }
+    else if args.flag_keep_newer {
+        OverwritePolicy::KeepNewer
+    }
+    else if args.flag_rename {
+        OverwritePolicy::Rename
+    }
+    else if args.flag_skip_existing {
+        OverwritePolicy::SkipExisting
+    }
+    else {
+        OverwritePolicy::Ask
+    }
+}
+
 /// Validate and return the command-line arguments.
 fn valid_args() -> Args {
     let pkg_name = option_env!("CARGO_PKG_NAME").unwrap_or(PKG_NAME);
@@ -166,10 +337,14 @@ fn valid_args() -> Args {
     let docopt = Docopt::new(USAGE).unwrap();
     let usage = docopt.parser().usage.to_string();
     let args: Args = docopt.version(Some(version)).deserialize()
-        .unwrap_or_else(|error| error.exit());
+        .unwrap_or_else(|error| exit_with_usage(error));
     if is_extract_command(&args) && !valid_output(&args.flag_output) {
         let error = WithProgramUsage(Box::new(Argv("The output argument should be a directory.".to_string())), usage);
-        error.exit();
+        exit_with_usage(error);
+    }
+    if filename_encoding(&args.flag_filename_encoding).is_none() {
+        let message = format!("Unknown filename encoding {}.", args.flag_filename_encoding);
+        exit_with_usage(WithProgramUsage(Box::new(Argv(message)), usage));
     }
     args
 }
@@ -0,0 +1,476 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Output of the inspection commands, as text, JSON or CSV.
+
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+use uncbv::{Error as ArchiveError, Header};
+
+/// Output format of the inspection commands.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Text,
+}
+
+impl Format {
+    /// Get the output of the prompts, which is not stdout when it is read by another program.
+    pub fn prompt_output(self) -> Box<dyn Write> {
+        if self == Format::Text {
+            Box::new(io::stdout())
+        }
+        else {
+            Box::new(io::stderr())
+        }
+    }
+}
+
+/// Compression statistics of the blocks of a file.
+#[derive(Serialize)]
+pub struct BlockRecord {
+    pub block_count: usize,
+    pub huffman_block_count: usize,
+    pub lz_block_count: usize,
+}
+
+/// A file of the archive.
+#[derive(Serialize)]
+pub struct FileRecord {
+    pub filename: String,
+    pub compressed_size: i32,
+    pub decompressed_size: i32,
+    /// Modification time, in seconds since the Unix epoch.
+    pub modified: Option<i64>,
+    #[serde(flatten)]
+    pub blocks: Option<BlockRecord>,
+}
+
+/// Archive header fields.
+#[derive(Serialize)]
+struct HeaderRecord {
+    file_count: usize,
+    record_size: usize,
+}
+
+impl From<&Header> for HeaderRecord {
+    fn from(header: &Header) -> HeaderRecord {
+        HeaderRecord {
+            file_count: header.file_count(),
+            record_size: header.filename_len(),
+        }
+    }
+}
+
+/// Kind of a file given to the `identify` command.
+#[derive(Serialize)]
+pub struct IdentifyRecord {
+    pub filename: String,
+    pub kind: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+/// Summary of an archive.
+#[derive(Serialize)]
+pub struct InfoRecord {
+    pub encrypted: bool,
+    pub file_count: usize,
+    pub record_size: usize,
+    pub compressed_size: i64,
+    pub decompressed_size: i64,
+}
+
+/// Result of the verification of a file.
+#[derive(Serialize)]
+pub struct TestRecord {
+    pub filename: String,
+    pub ok: bool,
+    pub error: Option<ErrorRecord>,
+}
+
+/// Columns of the errors in CSV format.
+const ERROR_COLUMNS: [&str; 8] = ["error", "kind", "entry", "block_index", "offset", "actual", "expected", "reason"];
+
+/// Error which occurred while processing a file, with the context of an archive error.
+#[derive(Serialize)]
+pub struct ErrorRecord {
+    error: String,
+    /// Variant of the archive error, or kind of the I/O error.
+    kind: String,
+    entry: Option<String>,
+    block_index: Option<usize>,
+    offset: Option<usize>,
+    actual: Option<usize>,
+    expected: Option<i32>,
+    reason: Option<&'static str>,
+}
+
+impl ErrorRecord {
+    pub fn new(error: &Error) -> ErrorRecord {
+        let mut record = ErrorRecord {
+            error: error.to_string(),
+            kind: format!("{:?}", error.kind()),
+            entry: None,
+            block_index: None,
+            offset: None,
+            actual: None,
+            expected: None,
+            reason: None,
+        };
+        let archive_error =
+            match error.get_ref().and_then(|error| error.downcast_ref::<ArchiveError>()) {
+                Some(error) => error,
+                None => return record,
+            };
+        let kind =
+            match *archive_error {
+                ArchiveError::BadBlock { ref entry, block_index, offset } => {
+                    record.set_block(entry, block_index, offset);
+                    "BadBlock"
+                },
+                ArchiveError::BadChecksum { ref entry, block_index, offset } => {
+                    record.set_block(entry, block_index, offset);
+                    "BadChecksum"
+                },
+                ArchiveError::BadFileList => "BadFileList",
+                ArchiveError::BadMagic => "BadMagic",
+                ArchiveError::CompressedSize { ref entry, actual, expected } => {
+                    record.set_size(entry, actual, expected);
+                    "CompressedSize"
+                },
+                ArchiveError::DecompressedSize { ref entry, actual, expected } => {
+                    record.set_size(entry, actual, expected);
+                    "DecompressedSize"
+                },
+                ArchiveError::HuffmanTree { ref entry, block_index, offset } => {
+                    record.set_block(entry, block_index, offset);
+                    "HuffmanTree"
+                },
+                // NOTE: the I/O errors keep their kind.
+                ArchiveError::Io(_) => return record,
+                ArchiveError::TruncatedBlock { ref entry, block_index, offset } => {
+                    record.set_block(entry, block_index, offset);
+                    "TruncatedBlock"
+                },
+                ArchiveError::TruncatedFileList => "TruncatedFileList",
+                ArchiveError::TruncatedHeader => "TruncatedHeader",
+                ArchiveError::UnsafeFilename { ref filename, reason } => {
+                    record.entry = Some(filename.clone());
+                    record.reason = Some(reason);
+                    "UnsafeFilename"
+                },
+                ArchiveError::WrongPassword => "WrongPassword",
+            };
+        record.kind = kind.to_string();
+        record
+    }
+
+    /// Set the context of an error in a block.
+    fn set_block(&mut self, entry: &str, block_index: usize, offset: usize) {
+        self.entry = Some(entry.to_string());
+        self.block_index = Some(block_index);
+        self.offset = Some(offset);
+    }
+
+    /// Set the context of an error in the size of an entry.
+    fn set_size(&mut self, entry: &str, actual: usize, expected: i32) {
+        self.entry = Some(entry.to_string());
+        self.actual = Some(actual);
+        self.expected = Some(expected);
+    }
+    /// Get the fields of the `ERROR_COLUMNS` in CSV format.
+    fn csv_fields(&self) -> Vec<String> {
+        vec![self.error.clone(), self.kind.clone(), self.entry.clone().unwrap_or_default(),
+            optional_field(self.block_index), optional_field(self.offset), optional_field(self.actual),
+            optional_field(self.expected), self.reason.unwrap_or_default().to_string()]
+    }
+}
+
+/// Show the error which occurred while processing `filename`.
+/// The errors in text format are written to stderr.
+pub fn print_error(format: Format, filename: &str, error: &Error) {
+    // NOTE: the error cannot be reported if it cannot be written.
+    let _ = write_stdout(|output| print_error_to(output, format, filename, error));
+}
+
+fn print_error_to(output: &mut dyn Write, format: Format, filename: &str, error: &Error) -> Result<(), Error> {
+    match format {
+        Format::Csv => {
+            let mut row = vec![filename.to_string()];
+            row.extend(ErrorRecord::new(error).csv_fields());
+            print_csv(output, &[&["filename"][..], &ERROR_COLUMNS].concat(), vec![row])
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct FileError<'a> {
+                filename: &'a str,
+                #[serde(flatten)]
+                error: ErrorRecord,
+            }
+
+            print_json(output, &FileError {
+                filename,
+                error: ErrorRecord::new(error),
+            })
+        },
+        Format::Text => {
+            eprintln!("{}: {}", filename, error);
+            Ok(())
+        },
+    }
+}
+
+/// Show the kind of the files.
+pub fn print_identify(format: Format, results: &[IdentifyRecord]) -> Result<(), Error> {
+    write_stdout(|output| match format {
+        Format::Csv => {
+            let rows = results.iter()
+                .map(|result| vec![result.filename.clone(), result.kind.unwrap_or_default().to_string(),
+                    result.error.clone().unwrap_or_default()])
+                .collect();
+            print_csv(output, &["filename", "kind", "error"], rows)
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct IdentifyResults<'a> {
+                files: &'a [IdentifyRecord],
+            }
+
+            print_json(output, &IdentifyResults {
+                files: results,
+            })
+        },
+        Format::Text => {
+            for result in results {
+                match result.error {
+                    Some(ref error) => eprintln!("{}: {}", result.filename, error),
+                    None => writeln!(output, "{}: {}", result.filename, result.kind.unwrap_or_default())?,
+                }
+            }
+            Ok(())
+        },
+    })
+}
+
+/// Show the summary of an archive.
+pub fn print_info(format: Format, info: &InfoRecord) -> Result<(), Error> {
+    write_stdout(|output| match format {
+        Format::Csv => {
+            print_csv(output, &["encrypted", "file_count", "record_size", "compressed_size", "decompressed_size"],
+                vec![vec![info.encrypted.to_string(), info.file_count.to_string(), info.record_size.to_string(),
+                    info.compressed_size.to_string(), info.decompressed_size.to_string()]])
+        },
+        Format::Json => print_json(output, info),
+        Format::Text => {
+            writeln!(output, "Encrypted:         {}", if info.encrypted { "yes" } else { "no" })?;
+            writeln!(output, "Files:             {}", info.file_count)?;
+            writeln!(output, "Record size:       {}", info.record_size)?;
+            writeln!(output, "Compressed size:   {}", info.compressed_size)?;
+            writeln!(output, "Decompressed size: {}", info.decompressed_size)?;
+            writeln!(output, "Ratio:             {}", ratio(info.compressed_size, info.decompressed_size))
+        },
+    })
+}
+
+/// Show the files of an archive.
+/// The text output shows only the filenames, unless `verbose` is set.
+pub fn print_list(format: Format, header: &Header, files: &[FileRecord], verbose: bool) -> Result<(), Error> {
+    write_stdout(|output| match format {
+        Format::Csv => {
+            let mut columns = vec!["filename", "compressed_size", "decompressed_size", "modified"];
+            if verbose {
+                columns.extend_from_slice(&["block_count", "huffman_block_count", "lz_block_count"]);
+            }
+            // NOTE: the header fields are repeated on every row, to keep a single kind of record.
+            columns.extend_from_slice(&["file_count", "record_size"]);
+            let header = HeaderRecord::from(header);
+            let rows = files.iter()
+                .map(|file| {
+                    let mut row = vec![file.filename.clone(), file.compressed_size.to_string(),
+                        file.decompressed_size.to_string(), optional_field(file.modified)];
+                    if let Some(ref blocks) = file.blocks {
+                        row.extend_from_slice(&[blocks.block_count.to_string(), blocks.huffman_block_count.to_string(),
+                            blocks.lz_block_count.to_string()]);
+                    }
+                    row.extend_from_slice(&[header.file_count.to_string(), header.record_size.to_string()]);
+                    row
+                })
+                .collect();
+            print_csv(output, &columns, rows)
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct ListRecord<'a> {
+                header: HeaderRecord,
+                files: &'a [FileRecord],
+            }
+
+            print_json(output, &ListRecord {
+                header: header.into(),
+                files,
+            })
+        },
+        Format::Text if verbose => print_table(output, files),
+        Format::Text => {
+            for file in files {
+                writeln!(output, "{}", file.filename)?;
+            }
+            Ok(())
+        },
+    })
+}
+
+/// Show the results of the verification of the files.
+pub fn print_test(format: Format, results: &[TestRecord]) -> Result<(), Error> {
+    write_stdout(|output| match format {
+        Format::Csv => {
+            let rows = results.iter()
+                .map(|result| {
+                    let mut row = vec![result.filename.clone(), status(result).to_string()];
+                    match result.error {
+                        Some(ref error) => row.extend(error.csv_fields()),
+                        None => row.resize(2 + ERROR_COLUMNS.len(), String::new()),
+                    }
+                    row
+                })
+                .collect();
+            print_csv(output, &[&["filename", "status"][..], &ERROR_COLUMNS].concat(), rows)
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct TestResults<'a> {
+                files: &'a [TestRecord],
+                ok: bool,
+            }
+
+            print_json(output, &TestResults {
+                files: results,
+                ok: results.iter().all(|result| result.ok),
+            })
+        },
+        Format::Text => {
+            for result in results {
+                match result.error {
+                    Some(ref error) => writeln!(output, "{:<8}{}: {}", status(result), result.filename, error.error)?,
+                    None => writeln!(output, "{:<8}{}", status(result), result.filename)?,
+                }
+            }
+            Ok(())
+        },
+    })
+}
+
+/// Quote a CSV field if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_string()
+    }
+}
+
+/// Get the number of seconds between the Unix epoch and `time`.
+pub fn unix_time(time: Option<SystemTime>) -> Option<i64> {
+    time.map(|time|
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(error) => -(error.duration().as_secs() as i64),
+        })
+}
+
+/// Format an optional CSV field, which is empty when missing.
+fn optional_field<T: ToString>(field: Option<T>) -> String {
+    field.map(|field| field.to_string()).unwrap_or_default()
+}
+
+fn print_csv(output: &mut dyn Write, columns: &[&str], rows: Vec<Vec<String>>) -> Result<(), Error> {
+    writeln!(output, "{}", columns.join(","))?;
+    for row in rows {
+        let fields: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+        writeln!(output, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+fn print_json<T: Serialize>(output: &mut dyn Write, value: &T) -> Result<(), Error> {
+    // NOTE: the records only contain strings and numbers, so they can always be serialized.
+    writeln!(output, "{}", serde_json::to_string_pretty(value).unwrap())
+}
+
+/// Show the files with their sizes and the compression of their blocks, followed by the totals.
+fn print_table(output: &mut dyn Write, files: &[FileRecord]) -> Result<(), Error> {
+    writeln!(output, "{:>12} {:>12} {:>6} {:>6} {:>7} {:>6}  Name", "Compressed", "Decompressed", "Ratio", "Blocks",
+        "Huffman", "LZ")?;
+    let mut total_compressed_size = 0;
+    let mut total_decompressed_size = 0;
+    let mut total_block_count = 0;
+    let mut total_huffman_count = 0;
+    let mut total_lz_count = 0;
+    for file in files {
+        let compressed_size = file.compressed_size as i64;
+        let decompressed_size = file.decompressed_size as i64;
+        let (block_count, huffman_count, lz_count) = match file.blocks {
+            Some(ref blocks) => (blocks.block_count, blocks.huffman_block_count, blocks.lz_block_count),
+            None => (0, 0, 0),
+        };
+        writeln!(output, "{:>12} {:>12} {:>6} {:>6} {:>7} {:>6}  {}", compressed_size, decompressed_size,
+            ratio(compressed_size, decompressed_size), block_count, huffman_count, lz_count, file.filename)?;
+        total_compressed_size += compressed_size;
+        total_decompressed_size += decompressed_size;
+        total_block_count += block_count;
+        total_huffman_count += huffman_count;
+        total_lz_count += lz_count;
+    }
+    writeln!(output, "{:>12} {:>12} {:>6} {:>6} {:>7} {:>6}  {} files", total_compressed_size,
+        total_decompressed_size, ratio(total_compressed_size, total_decompressed_size), total_block_count,
+        total_huffman_count, total_lz_count, files.len())
+}
+
+/// Format the compressed size as a percentage of the decompressed size.
+fn ratio(compressed_size: i64, decompressed_size: i64) -> String {
+    if decompressed_size == 0 {
+        "-".to_string()
+    }
+    else {
+        format!("{:.1}%", compressed_size as f64 * 100.0 / decompressed_size as f64)
+    }
+}
+
+fn status(result: &TestRecord) -> &'static str {
+    if result.ok {
+        "OK"
+    }
+    else {
+        "FAIL"
+    }
+}
+
+/// Write to the locked stdout with `write`.
+/// A reader of the output which does not want more data is not an error, like with `cat`.
+fn write_stdout<F: FnOnce(&mut dyn Write) -> Result<(), Error>>(write: F) -> Result<(), Error> {
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    match write(&mut output).and_then(|()| output.flush()) {
+        Err(ref error) if error.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
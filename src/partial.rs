@@ -0,0 +1,135 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Files written under a temporary name and renamed once complete.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, create_dir_all, remove_file, rename};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Temporary files which are being written.
+static PARTIAL_FILES: Mutex<PartialFiles> = Mutex::new(PartialFiles {
+    paths: Vec::new(),
+    removed: false,
+});
+
+/// Number used to give a unique name to the temporary files.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct PartialFiles {
+    paths: Vec<PathBuf>,
+    /// The files have been removed by `remove_partial_files()`, so no new file can be created.
+    removed: bool,
+}
+
+/// A file written in a temporary file next to its final path.
+/// The temporary file is renamed by `commit()` and removed if the file is dropped before.
+pub struct PartialFile {
+    /// The temporary file has been renamed to `path`.
+    committed: bool,
+    file: Option<BufWriter<File>>,
+    path: PathBuf,
+    temp_path: PathBuf,
+}
+
+impl PartialFile {
+    /// Create the temporary file of `path`, and the directories of `path`.
+    pub fn create(path: &Path) -> Result<PartialFile, Error> {
+        if let Some(directory) = path.parent() {
+            create_dir_all(directory)?;
+        }
+        let filename = path.file_name()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Invalid path {}", path.display())))?;
+        let mut temp_filename = OsString::from(".");
+        temp_filename.push(filename);
+        temp_filename.push(format!(".{}-{}.part", process::id(), NEXT_ID.fetch_add(1, Ordering::SeqCst)));
+        let temp_path = path.with_file_name(temp_filename);
+
+        // NOTE: the file is created while the list is locked, so that it cannot be created after
+        // the interruption cleanup.
+        let mut partial_files = lock_partial_files();
+        if partial_files.removed {
+            return Err(Error::new(ErrorKind::Interrupted, "Extraction interrupted"));
+        }
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        partial_files.paths.push(temp_path.clone());
+        Ok(PartialFile {
+            committed: false,
+            file: Some(BufWriter::new(file)),
+            path: path.to_path_buf(),
+            temp_path,
+        })
+    }
+
+    /// Write the remaining data and move the temporary file to the final path.
+    /// On error, the temporary file is removed when `self` is dropped.
+    pub fn commit(mut self) -> Result<(), Error> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        rename(&self.temp_path, &self.path)
+            .map_err(|error| Error::new(error.kind(), format!("{}: {}", self.path.display(), error)))?;
+        self.committed = true;
+        lock_partial_files().paths.retain(|path| *path != self.temp_path);
+        Ok(())
+    }
+
+    fn file(&mut self) -> &mut BufWriter<File> {
+        // NOTE: the file is only taken by commit(), which consumes self.
+        self.file.as_mut().unwrap()
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            // NOTE: the file is closed before being removed.
+            self.file.take();
+            let mut partial_files = lock_partial_files();
+            let _ = remove_file(&self.temp_path);
+            partial_files.paths.retain(|path| *path != self.temp_path);
+        }
+    }
+}
+
+impl Write for PartialFile {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        self.file().write(buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file().flush()
+    }
+}
+
+fn lock_partial_files() -> MutexGuard<'static, PartialFiles> {
+    PARTIAL_FILES.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Remove the temporary files of the files being extracted and prevent the creation of new ones.
+/// This is meant to be called when the program is interrupted.
+pub fn remove_partial_files() {
+    let mut partial_files = lock_partial_files();
+    partial_files.removed = true;
+    for path in partial_files.paths.drain(..) {
+        let _ = remove_file(path);
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Sources of the password of the encrypted archives.
+
+use std::env;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, IsTerminal, Write};
+
+use rpassword;
+use uncbv::check_password;
+
+use commands::usage_error;
+
+/// Environment variable containing the password.
+const PASSWORD_VARIABLE: &str = "UNCBV_PASSWORD";
+
+/// Error returned when none of the passwords of a list decrypts the archive.
+#[derive(Debug)]
+pub struct NoValidPassword {
+    /// Path of the list of passwords.
+    path: String,
+}
+
+impl Display for NoValidPassword {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "None of the passwords of {} is valid", self.path)
+    }
+}
+
+impl error::Error for NoValidPassword {
+}
+
+/// Where the password of the encrypted archives comes from.
+#[derive(Clone)]
+pub enum PasswordSource {
+    /// Read the first line of a file descriptor.
+    Fd(i32),
+    /// Read the first line of a file.
+    File(String),
+    /// Try every line of a file until one decrypts the archive.
+    List(String),
+    /// Ask the password to the user.
+    Prompt,
+    /// The password given on the command line or in the environment.
+    Value(String),
+}
+
+impl PasswordSource {
+    /// Select the source of the password from the command-line arguments.
+    /// Without any, the password is taken from the `UNCBV_PASSWORD` environment variable, or asked
+    /// to the user.
+    pub fn new(password: Option<String>, password_file: Option<String>, password_fd: Option<i32>,
+        password_list: Option<String>) -> PasswordSource
+    {
+        if let Some(password) = password {
+            PasswordSource::Value(password)
+        }
+        else if let Some(path) = password_file {
+            PasswordSource::File(path)
+        }
+        else if let Some(path) = password_list {
+            PasswordSource::List(path)
+        }
+        else if let Some(fd) = password_fd {
+            PasswordSource::Fd(fd)
+        }
+        else if let Ok(password) = env::var(PASSWORD_VARIABLE) {
+            PasswordSource::Value(password)
+        }
+        else {
+            PasswordSource::Prompt
+        }
+    }
+
+    /// Get the password of the encrypted archive `filename`.
+    /// With a list of passwords, the first one decrypting the archive is used and its line is shown
+    /// on stderr. Otherwise, the prompt is shown in `output`.
+    pub fn find<W: Write>(&self, filename: &str, output: W) -> Result<String, Error> {
+        match *self {
+            PasswordSource::List(ref path) =>
+                find_in_list(path, filename)?
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, NoValidPassword { path: path.clone() })),
+            _ => self.read(output),
+        }
+    }
+
+    /// Get the passwords given on the command line, in a file or in the environment, without asking
+    /// the user.
+    pub fn given(&self) -> Result<Vec<String>, Error> {
+        match *self {
+            PasswordSource::List(ref path) => {
+                let mut passwords = vec![];
+                for password in BufReader::new(File::open(path)?).lines() {
+                    passwords.push(password?.trim_end_matches('\r').to_string());
+                }
+                Ok(passwords)
+            },
+            PasswordSource::Prompt => Ok(vec![]),
+            _ => Ok(vec![self.read(io::sink())?]),
+        }
+    }
+
+    /// Get the password.
+    /// The password typed by the user is asked with `prompt()`.
+    pub fn read<W: Write>(&self, prompt_output: W) -> Result<String, Error> {
+        match *self {
+            PasswordSource::Fd(fd) => read_line(BufReader::new(open_fd(fd)?)),
+            PasswordSource::File(ref path) => read_line(BufReader::new(File::open(path)?)),
+            PasswordSource::List(_) =>
+                Err(usage_error("A list of passwords can only be used to decrypt an archive")),
+            PasswordSource::Prompt => prompt("Password", prompt_output),
+            PasswordSource::Value(ref password) => Ok(password.clone()),
+        }
+    }
+
+    /// Get a new password, which is asked twice when it is typed by the user.
+    /// Returns an error if the two passwords differ.
+    pub fn read_new<W: Write>(&self, mut prompt_output: W) -> Result<String, Error> {
+        let password = self.read(&mut prompt_output)?;
+        if let PasswordSource::Prompt = *self {
+            if prompt("Confirm password", prompt_output)? != password {
+                return Err(usage_error("The passwords do not match"));
+            }
+        }
+        Ok(password)
+    }
+}
+
+/// Get the first password of the list at `path` which decrypts `filename`, showing its line on
+/// stderr.
+fn find_in_list(path: &str, filename: &str) -> Result<Option<String>, Error> {
+    let passwords = BufReader::new(File::open(path)?);
+    for (index, password) in passwords.lines().enumerate() {
+        let password = password?;
+        let password = password.trim_end_matches('\r');
+        if check_password(File::open(filename)?, password)? {
+            // NOTE: the message is not written in the output, which may be parsed.
+            eprintln!("Using the password on line {} of {}", index + 1, path);
+            return Ok(Some(password.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Result<File, Error> {
+    // NOTE: open a new file instead of taking the ownership of the file descriptor, which may not be
+    // open.
+    File::open(format!("/dev/fd/{}", fd))
+        .map_err(|error| Error::new(error.kind(), format!("Cannot read the file descriptor {}: {}", fd, error)))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Result<File, Error> {
+    Err(Error::new(ErrorKind::Unsupported, "Reading the password from a file descriptor is not supported on this platform"))
+}
+
+/// Ask the password to the user with `message`.
+/// When stdin is a terminal, the password is read from it without echo. Otherwise, the prompt is
+/// shown in `output` and the password is read from the first line of stdin.
+fn prompt<W: Write>(message: &str, mut output: W) -> Result<String, Error> {
+    if io::stdin().is_terminal() {
+        rpassword::prompt_password(format!("{}: ", message))
+    }
+    else {
+        writeln!(output, "{}:", message)?;
+        // NOTE: stdin is not wrapped in a new buffer, which would consume the next answers.
+        read_line(io::stdin().lock())
+    }
+}
+
+/// Read the first line of `reader`, without the line terminator.
+fn read_line<R: BufRead>(mut reader: R) -> Result<String, Error> {
+    let mut password = String::new();
+    if reader.read_line(&mut password)? == 0 {
+        return Err(usage_error("No password given"));
+    }
+    let len = password.trim_end_matches(['\r', '\n']).len();
+    password.truncate(len);
+    Ok(password)
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Threads decoding the blocks of the extracted files.
+
+use std::borrow::Cow;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+use archive::block_error;
+use cbv::{self, BlockError};
+use error::Error;
+use partial::PartialFile;
+
+/// Maximum number of decompression threads per CPU.
+const MAX_JOBS_PER_CPU: usize = 4;
+
+/// Decoded block sent back by a decompression thread.
+type DecodedBlock = (usize, Result<Vec<u8>, BlockError>);
+
+/// Compressed block sent to the decompression threads.
+type Task<'a> = (usize, Cow<'a, [u8]>);
+
+/// Sender of the blocks to the decompression threads, which writes the decoded blocks in order.
+/// At most `2 * jobs` blocks are sent before the blocks preceding them are written, so the memory
+/// used does not depend on the size of the archive, even when a block is slow to decode.
+pub struct BlockDecoder<'a, 'b> {
+    /// Blocks received before the previous ones.
+    decoded: HashMap<usize, Result<Vec<u8>, BlockError>>,
+    jobs: usize,
+    results: &'b Receiver<DecodedBlock>,
+    /// Number of blocks sent.
+    sent: usize,
+    tasks: SyncSender<Task<'a>>,
+    /// Number of blocks written.
+    written: usize,
+}
+
+impl<'a, 'b> BlockDecoder<'a, 'b> {
+    /// Write the remaining blocks of `entry` to `file`.
+    pub fn finish_entry(&mut self, entry: &EntryBlocks, file: &mut PartialFile) -> Result<(), Error> {
+        let count = self.sent;
+        self.write_blocks(entry, file, count)
+    }
+
+    /// Send the block of `entry` at `offset` in the archive, whose bytes are `bytes`, to the
+    /// decompression threads, and write the decoded blocks of `entry` to `file` until at most
+    /// `2 * jobs` blocks are pending.
+    pub fn send(&mut self, entry: &mut EntryBlocks, offset: usize, bytes: Cow<'a, [u8]>, file: &mut PartialFile)
+        -> Result<(), Error>
+    {
+        entry.offsets.push(offset);
+        self.tasks.send((self.sent, bytes))
+            .map_err(|_| thread_stopped())?;
+        self.sent += 1;
+        let count = self.sent.saturating_sub(2 * self.jobs);
+        self.write_blocks(entry, file, count)
+    }
+
+    /// Start sending the blocks of the entry `name`.
+    pub fn start_entry(&self, name: &str) -> EntryBlocks {
+        EntryBlocks {
+            first_block: self.sent,
+            name: name.to_string(),
+            offsets: vec![],
+        }
+    }
+
+    /// Write the blocks of `entry` to `file`, in order, until `count` blocks are written.
+    fn write_blocks(&mut self, entry: &EntryBlocks, file: &mut PartialFile, count: usize) -> Result<(), Error> {
+        while self.written < count {
+            let bytes = loop {
+                if let Some(bytes) = self.decoded.remove(&self.written) {
+                    break bytes;
+                }
+                let (index, bytes) = self.results.recv()
+                    .map_err(|_| thread_stopped())?;
+                self.decoded.insert(index, bytes);
+            };
+            let block_index = self.written - entry.first_block;
+            let offset = entry.offsets[block_index];
+            let bytes = bytes.map_err(|error| block_error(&entry.name, block_index, offset, error))?;
+            file.write_all(&bytes)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+/// The blocks of an entry sent to the decompression threads.
+pub struct EntryBlocks {
+    /// Index of the first block of the entry among every block sent to the threads.
+    first_block: usize,
+    name: String,
+    /// Position of every block in the archive.
+    offsets: Vec<usize>,
+}
+
+/// Start `jobs` decompression threads, but at most `MAX_JOBS_PER_CPU` per CPU, and call
+/// `send_blocks` with the `BlockDecoder` sending them the blocks.
+/// The threads stop when `send_blocks` returns.
+pub fn decode_blocks<'a, F>(jobs: usize, send_blocks: F) -> Result<(), Error>
+    where F: FnOnce(&mut BlockDecoder<'a, '_>) -> Result<(), Error>
+{
+    // NOTE: more threads would not decode the blocks faster, and too many threads cannot be
+    // started.
+    let cpu_count = thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+    let jobs = min(jobs, cpu_count * MAX_JOBS_PER_CPU);
+    let (task_sender, task_receiver) = mpsc::sync_channel::<Task<'a>>(jobs);
+    let task_receiver = Mutex::new(task_receiver);
+    let (result_sender, result_receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0 .. jobs {
+            let result_sender = result_sender.clone();
+            let task_receiver = &task_receiver;
+            let spawned = thread::Builder::new().spawn_scoped(scope, move || {
+                loop {
+                    // NOTE: the lock is released before decoding the block.
+                    let task = task_receiver.lock().unwrap().recv();
+                    // Stop when the sender is gone because every block is sent or because of an
+                    // error.
+                    let (index, bytes) =
+                        match task {
+                            Ok(task) => task,
+                            Err(_) => break,
+                        };
+                    if result_sender.send((index, cbv::decode_block(&bytes))).is_err() {
+                        break;
+                    }
+                }
+            });
+            // NOTE: the threads already started stop when the task sender is dropped on return.
+            spawned.map_err(|error|
+                Error::Io(io::Error::new(error.kind(), format!("Cannot start a decompression thread: {}", error))))?;
+        }
+        drop(result_sender);
+
+        let mut decoder = BlockDecoder {
+            decoded: HashMap::new(),
+            jobs,
+            results: &result_receiver,
+            sent: 0,
+            tasks: task_sender,
+            written: 0,
+        };
+        send_blocks(&mut decoder)
+    })
+}
+
+fn thread_stopped() -> Error {
+    Error::Io(io::Error::other("A decompression thread stopped unexpectedly"))
+}
@@ -0,0 +1,264 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Streaming CBV archive reader.
+
+use std::borrow::Cow;
+use std::cmp::min;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+
+use encoding::EncodingRef;
+use encoding::all::WINDOWS_1252;
+
+use archive::{block_error, parse_file_list_with_encoding, parse_header};
+use cbv::{self, BLOCK_HEADER_SIZE, FileMetaData, HEADER_SIZE, Header};
+use error::Error;
+use partial::PartialFile;
+use pool::{self, BlockDecoder};
+
+/// An archive read sequentially from a stream, like a pipe.
+/// Only the header, the file list and the current block are kept in memory.
+pub struct ArchiveReader<R> {
+    /// Decompressed content of the current block.
+    block: Vec<u8>,
+    /// Index of the next block of the current entry.
+    block_index: usize,
+    /// Compressed bytes of the last block read.
+    buffer: Vec<u8>,
+    /// Position of the end of the current entry in the archive.
+    entry_end: usize,
+    file_list: Vec<FileMetaData>,
+    header: Header,
+    /// Index of the next entry in the file list.
+    next_index: usize,
+    /// Position of `reader` in the archive.
+    offset: usize,
+    /// Position in `block`.
+    position: usize,
+    reader: R,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Read the header and the file list of the archive from `reader`.
+    /// The filenames are decoded from the Windows code page 1252.
+    pub fn new(reader: R) -> Result<ArchiveReader<R>, Error> {
+        ArchiveReader::with_filename_encoding(reader, WINDOWS_1252)
+    }
+
+    /// Read the header and the file list of the archive from `reader`, decoding the filenames with
+    /// `encoding`.
+    pub fn with_filename_encoding(mut reader: R, encoding: EncodingRef) -> Result<ArchiveReader<R>, Error> {
+        let mut bytes = vec![];
+        reader.by_ref().take(HEADER_SIZE as u64).read_to_end(&mut bytes)?;
+        let header = parse_header(&bytes)?;
+        bytes.clear();
+        reader.by_ref().take(header.total_size() as u64).read_to_end(&mut bytes)?;
+        let file_list = parse_file_list_with_encoding(&bytes, &header, encoding)?;
+        let offset = HEADER_SIZE + header.total_size();
+        Ok(ArchiveReader {
+            block: vec![],
+            block_index: 0,
+            buffer: vec![],
+            entry_end: offset,
+            file_list,
+            header,
+            next_index: 0,
+            offset,
+            position: 0,
+            reader,
+        })
+    }
+
+    /// Decompress the entries to the paths of `paths`, which is in the order of the file list,
+    /// skipping the entries without a path.
+    /// With more than one job, the blocks are decoded by `jobs` threads while the next ones are
+    /// read, keeping at most `2 * jobs` blocks in memory.
+    pub fn extract_to(&mut self, paths: &[Option<PathBuf>], jobs: usize) -> Result<(), Error> {
+        if jobs <= 1 {
+            for path in paths {
+                let mut entry =
+                    match self.next_entry()? {
+                        Some(entry) => entry,
+                        None => break,
+                    };
+                if let Some(ref path) = *path {
+                    let mut file = PartialFile::create(path)?;
+                    entry.decompress(&mut file)?;
+                    file.commit()?;
+                }
+            }
+            return Ok(());
+        }
+
+        pool::decode_blocks(jobs, |decoder| self.extract_blocks(paths, decoder))
+    }
+
+    /// Get the file list.
+    pub fn file_list(&self) -> &[FileMetaData] {
+        &self.file_list
+    }
+
+    /// Get the archive header.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Skip the rest of the current entry and get the next one, or `None` after the last entry.
+    pub fn next_entry(&mut self) -> Result<Option<StreamEntry<'_, R>>, Error> {
+        self.skip_entry()?;
+        let compressed_size =
+            match self.file_list.get(self.next_index) {
+                Some(file) => file.compressed_size,
+                None => return Ok(None),
+            };
+        self.entry_end = self.offset.saturating_add(compressed_size.max(0) as usize);
+        self.next_index += 1;
+        self.block.clear();
+        self.block_index = 0;
+        self.position = 0;
+        Ok(Some(StreamEntry {
+            archive: self,
+        }))
+    }
+
+    /// Get the name of the current entry.
+    fn entry_name(&self) -> &str {
+        &self.file_list[self.next_index - 1].filename
+    }
+
+    /// Read the blocks of the entries which have a path in `paths` and send them to `decoder`.
+    fn extract_blocks(&mut self, paths: &[Option<PathBuf>], decoder: &mut BlockDecoder) -> Result<(), Error> {
+        for path in paths {
+            if self.next_entry()?.is_none() {
+                break;
+            }
+            let path =
+                match *path {
+                    Some(ref path) => path,
+                    None => continue,
+                };
+
+            let mut file = PartialFile::create(path)?;
+            let mut entry = decoder.start_entry(self.entry_name());
+            loop {
+                let offset = self.offset;
+                if !self.read_block()? {
+                    break;
+                }
+                self.block_index += 1;
+                decoder.send(&mut entry, offset, Cow::Owned(self.buffer.clone()), &mut file)?;
+            }
+            decoder.finish_entry(&entry, &mut file)?;
+            file.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Read the next compressed block of the current entry into `buffer`.
+    /// Returns false when there is no more block.
+    fn read_block(&mut self) -> Result<bool, Error> {
+        let remaining = self.entry_end - self.offset;
+        if remaining == 0 {
+            return Ok(false);
+        }
+
+        let block_offset = self.offset;
+        self.buffer.clear();
+        self.read_buffer(min(remaining, BLOCK_HEADER_SIZE))?;
+        if self.buffer.len() == BLOCK_HEADER_SIZE {
+            let size = self.buffer[0] as usize | (self.buffer[1] as usize) << 8;
+            self.read_buffer(min(remaining - BLOCK_HEADER_SIZE, size))?;
+            if self.buffer.len() == BLOCK_HEADER_SIZE + size {
+                return Ok(true);
+            }
+        }
+        Err(Error::TruncatedBlock {
+            entry: self.entry_name().to_string(),
+            block_index: self.block_index,
+            offset: block_offset,
+        })
+    }
+
+    /// Append at most `size` bytes read from the archive to `buffer`.
+    fn read_buffer(&mut self, size: usize) -> Result<(), Error> {
+        let count = self.reader.by_ref().take(size as u64).read_to_end(&mut self.buffer)?;
+        self.offset += count;
+        Ok(())
+    }
+
+    /// Read the decompressed content of the current entry.
+    fn read_entry(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        while self.position == self.block.len() {
+            let offset = self.offset;
+            if !self.read_block()? {
+                return Ok(0);
+            }
+            self.block = cbv::decode_block(&self.buffer)
+                .map_err(|error| block_error(self.entry_name(), self.block_index, offset, error))?;
+            self.block_index += 1;
+            self.position = 0;
+        }
+        let size = min(buffer.len(), self.block.len() - self.position);
+        buffer[..size].copy_from_slice(&self.block[self.position .. self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+
+    /// Skip the compressed content of the current entry which has not been read.
+    fn skip_entry(&mut self) -> Result<(), Error> {
+        let size = self.entry_end - self.offset;
+        let count = io::copy(&mut self.reader.by_ref().take(size as u64), &mut io::sink())?;
+        // NOTE: when the archive is truncated, the error is reported when reading the next entry.
+        self.offset += count as usize;
+        Ok(())
+    }
+}
+
+/// The current entry of an `ArchiveReader`, reading its decompressed content.
+/// It dereferences to its `FileMetaData`.
+pub struct StreamEntry<'a, R: 'a> {
+    archive: &'a mut ArchiveReader<R>,
+}
+
+impl<'a, R: Read> StreamEntry<'a, R> {
+    /// Decompress the file into `output`.
+    pub fn decompress<W: Write>(&mut self, output: &mut W) -> Result<(), Error> {
+        io::copy(self, output)?;
+        Ok(())
+    }
+
+    /// Get the file meta-data.
+    pub fn metadata(&self) -> &FileMetaData {
+        &self.archive.file_list[self.archive.next_index - 1]
+    }
+}
+
+impl<'a, R: Read> Deref for StreamEntry<'a, R> {
+    type Target = FileMetaData;
+
+    fn deref(&self) -> &FileMetaData {
+        self.metadata()
+    }
+}
+
+impl<'a, R: Read> Read for StreamEntry<'a, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        Ok(self.archive.read_entry(buffer)?)
+    }
+}
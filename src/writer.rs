@@ -0,0 +1,194 @@
+/*
+ * Copyright (C) 2016  Boucher, Antoni <bouanto@zoho.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! CBV archive writer.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use encoding::{EncoderTrap, Encoding};
+use encoding::all::WINDOWS_1252;
+
+use cbv::HEADER_SIZE;
+use compress::{BLOCK_SIZE, encode_block};
+
+/// Size of a file record in the file list.
+const FILE_RECORD_SIZE: usize = 173;
+/// Size of the filename field of a file record, including the null terminator.
+const FILENAME_SIZE: usize = 132;
+
+/// A file added to the archive, compressed in memory until the archive is written.
+struct CompressedFile {
+    data: Vec<u8>,
+    date: u32,
+    decompressed_size: usize,
+    filename: Vec<u8>,
+    time: u32,
+}
+
+/// Writer creating a CBV archive.
+/// The files are compressed as they are added and the archive is written by `finish()`.
+pub struct ArchiveWriter<W: Write> {
+    files: Vec<CompressedFile>,
+    huffman: bool,
+    output: W,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Create a writer which will write the archive in `output`.
+    pub fn new(output: W) -> ArchiveWriter<W> {
+        ArchiveWriter {
+            files: vec![],
+            huffman: true,
+            output,
+        }
+    }
+
+    /// Compress the content of `reader` as the file `filename`.
+    /// The directories in `filename` are separated by slashes.
+    pub fn add<R: Read>(&mut self, filename: &str, reader: R) -> Result<(), Error> {
+        self.add_with_time(filename, reader, SystemTime::now())
+    }
+
+    /// Compress the file at `path` as the file `filename`, keeping its modification time.
+    pub fn add_path<P: AsRef<Path>>(&mut self, filename: &str, path: P) -> Result<(), Error> {
+        let file = File::open(path)?;
+        let modified = file.metadata()?.modified()?;
+        self.add_with_time(filename, file, modified)
+    }
+
+    fn add_with_time<R: Read>(&mut self, filename: &str, mut reader: R, modified: SystemTime) -> Result<(), Error> {
+        let filename = filename_to_bytes(filename)?;
+        let mut data = vec![];
+        let mut decompressed_size = 0;
+        let mut buffer = vec![0; BLOCK_SIZE];
+        loop {
+            let size = read_block(&mut reader, &mut buffer)?;
+            if size == 0 {
+                break;
+            }
+            data.extend_from_slice(&encode_block(&buffer[..size], self.huffman));
+            decompressed_size += size;
+        }
+
+        if data.len() > i32::MAX as usize || decompressed_size > i32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "File too big for a CBV archive"));
+        }
+
+        let (date, time) = date_time(modified);
+        self.files.push(CompressedFile {
+            data,
+            date,
+            decompressed_size,
+            filename,
+            time,
+        });
+        Ok(())
+    }
+
+    /// Write the archive and return the output.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.files.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "Too many files for a CBV archive"));
+        }
+
+        let file_count = self.files.len() as u16;
+        self.output.write_all(&[0x08, 0x00, file_count as u8, (file_count >> 8) as u8, FILE_RECORD_SIZE as u8, 0x00, 0x03, 0x00])?;
+
+        let mut offset = (HEADER_SIZE + self.files.len() * FILE_RECORD_SIZE) as u64;
+        for file in &self.files {
+            let mut record = Vec::with_capacity(FILE_RECORD_SIZE);
+            record.extend_from_slice(&file.filename);
+            record.resize(FILENAME_SIZE, 0);
+            record.extend_from_slice(&(file.data.len() as i32).to_le_bytes());
+            record.extend_from_slice(&(file.decompressed_size as i32).to_le_bytes());
+            record.push(1);
+            record.extend_from_slice(&file.date.to_le_bytes());
+            record.extend_from_slice(&file.time.to_le_bytes());
+            record.extend_from_slice(&offset.to_le_bytes());
+            record.extend_from_slice(&(file.data.len() as u64).to_le_bytes());
+            record.extend_from_slice(&(file.decompressed_size as u64).to_le_bytes());
+            self.output.write_all(&record)?;
+            offset += file.data.len() as u64;
+        }
+
+        for file in &self.files {
+            self.output.write_all(&file.data)?;
+        }
+
+        Ok(self.output)
+    }
+
+    /// Enable or disable the Huffman coding of the blocks (enabled by default).
+    /// Huffman coding is only used for the blocks it makes smaller.
+    pub fn set_huffman(&mut self, huffman: bool) {
+        self.huffman = huffman;
+    }
+}
+
+/// Convert a modification time to the date (YYYYMMDD) and time (HHMMSScc) stored in the file
+/// records.
+fn date_time(modified: SystemTime) -> (u32, u32) {
+    let duration = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = duration.as_secs();
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = (seconds % 86_400) as u32;
+
+    // Convert the number of days since the epoch to a civil date.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date = (year * 10_000 + month * 100 + day) as u32;
+    let time = seconds_of_day / 3600 * 1_000_000 + seconds_of_day / 60 % 60 * 10_000 +
+        seconds_of_day % 60 * 100 + duration.subsec_nanos() / 10_000_000;
+    (date, time)
+}
+
+/// Convert the filename to the bytes stored in the archive, replacing the slashes by
+/// backslashes.
+fn filename_to_bytes(filename: &str) -> Result<Vec<u8>, Error> {
+    let filename = filename.replace('/', "\\");
+    let bytes = WINDOWS_1252.encode(&filename, EncoderTrap::Strict)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("Cannot encode the filename {}", filename)))?;
+    if bytes.is_empty() || bytes.len() >= FILENAME_SIZE || bytes.contains(&0) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid filename {}", filename)));
+    }
+    Ok(bytes)
+}
+
+/// Fill `buffer` from `reader`, stopping early only at the end of the file.
+fn read_block<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut size = 0;
+    while size < buffer.len() {
+        match reader.read(&mut buffer[size..]) {
+            Ok(0) => break,
+            Ok(count) => size += count,
+            Err(ref error) if error.kind() == ErrorKind::Interrupted => (),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(size)
+}
@@ -1,19 +1,230 @@
+// NOTE: the tests predate these lints and keep their original style.
+#![allow(clippy::needless_borrows_for_generic_args, clippy::redundant_field_names, clippy::redundant_static_lifetimes,
+    clippy::unnecessary_to_owned)]
+
 extern crate rand;
+extern crate serde_json;
+extern crate uncbv;
 extern crate walkdir;
 
 use std::env::{current_dir, temp_dir};
 use std::ffi::OsString;
-use std::fs::{File, create_dir_all, metadata, read_dir, remove_dir_all, remove_file};
-use std::io::{Read, Write};
+use std::fs::{File, copy, create_dir_all, metadata, read_dir, remove_dir_all, remove_file};
+use std::io::{Read, Write, pipe, sink};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rand::random;
+use serde_json::Value;
+use uncbv::{Archive, ArchiveReader, ArchiveWriter, Decryptor, Error, HEADER_SIZE, check_password, checksum, encrypt,
+    filename_encoding};
 use walkdir::WalkDir;
 
 const BUFFER_SIZE: usize = 4096;
 const DEFAULT_PASSWORD: &'static str = "password";
 
+#[test]
+fn archive_entries() {
+    let name = "tests/twic1134";
+    let archive = Archive::open(format!("{}.cbv", name)).unwrap();
+    assert_eq!(13, archive.len());
+
+    let mut filenames = vec![];
+    for entry in archive.entries() {
+        let mut expected = vec![];
+        File::open(format!("{}/{}", name, entry.filename)).unwrap()
+            .read_to_end(&mut expected).unwrap();
+        let mut actual = vec![];
+        entry.decompress(&mut actual).unwrap();
+        assert_eq!(entry.decompressed_size as usize, actual.len());
+        assert!(expected == actual);
+        filenames.push(entry.filename.clone());
+    }
+    filenames.sort();
+    assert_eq!(get_file_recursives(name), filenames);
+
+    let mut bytes = vec![];
+    File::open("tests/small.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    let archive = Archive::from_bytes(bytes).unwrap();
+    let temp_dir = TempDir::new();
+    archive.extract(&temp_dir.path).unwrap();
+    for file in get_file_recursives("tests/small") {
+        assert_file(format!("tests/small/{}", file), format!("{}/{}", temp_dir.as_str(), file));
+    }
+
+    let archive = Archive::from_reader(File::open("tests/decrypted_small.cbv").unwrap()).unwrap();
+    assert_eq!(12, archive.entries().count());
+
+    assert!(Archive::from_bytes(vec![0; 16]).is_err());
+}
+
+#[test]
+fn archive_entry() {
+    let name = "tests/twic1134";
+    let archive = Archive::open(format!("{}.cbv", name)).unwrap();
+
+    for filename in &["twic1134.cbh", "twic1134.ini", "twic1134.cba"] {
+        let entry = archive.entry(filename).unwrap();
+        assert_eq!(*filename, entry.filename);
+        let mut expected = vec![];
+        File::open(format!("{}/{}", name, filename)).unwrap()
+            .read_to_end(&mut expected).unwrap();
+        let mut actual = vec![];
+        entry.decompress(&mut actual).unwrap();
+        assert!(expected == actual);
+    }
+
+    for (index, entry) in archive.entries().enumerate() {
+        assert_eq!(entry.filename, archive.entry_at(index).unwrap().filename);
+    }
+
+    // The record of twic1134.cbh contains 2016-08-01 19:38:32.56.
+    let modified = archive.entry("twic1134.cbh").unwrap().modified.unwrap();
+    assert_eq!(Duration::new(1_470_080_312, 560_000_000), modified.duration_since(UNIX_EPOCH).unwrap());
+
+    assert!(archive.entry("missing.cbh").is_none());
+    assert!(archive.entry_at(archive.len()).is_none());
+}
+
+#[test]
+fn archive_errors() {
+    let mut bytes = vec![];
+    File::open("tests/twic1134.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    assert!(matches!(Archive::from_bytes(b"PK\x03\x04".to_vec()), Err(Error::BadMagic)));
+    assert!(matches!(Archive::from_bytes(bytes[..5].to_vec()), Err(Error::TruncatedHeader)));
+    assert!(matches!(Archive::from_bytes(bytes[..1000].to_vec()), Err(Error::TruncatedFileList)));
+
+    // An empty file is not memory-mapped.
+    let temp_dir = TempDir::new();
+    let empty = format!("{}/empty.cbv", temp_dir.as_str());
+    File::create(&empty).unwrap();
+    assert!(matches!(Archive::open(&empty), Err(Error::TruncatedHeader)));
+
+    // The errors of a reader are converted back to the library errors.
+    let archive = Archive::from_bytes(bytes[..200_000].to_vec()).unwrap();
+    let error = archive.entry("twic1134.cbg").unwrap().reader().read_to_end(&mut vec![]).unwrap_err();
+    match Error::from(error) {
+        Error::TruncatedBlock { entry, block_index, offset } =>
+            assert_eq!(("twic1134.cbg".to_string(), 1, 191_227), (entry, block_index, offset)),
+        error => panic!("Unexpected error: {}", error),
+    }
+
+    let index = bytes.len() - 3;
+    bytes[index] ^= 0xFF;
+    let archive = Archive::from_bytes(bytes).unwrap();
+    match archive.entry("twic1134.cbl").unwrap().verify() {
+        Err(Error::BadChecksum { entry, offset, .. }) =>
+            assert!(entry == "twic1134.cbl" && offset < index && index - offset < 65_540),
+        result => panic!("Unexpected result: {:?}", result),
+    }
+
+    // A block too small to contain its Huffman tree.
+    let mut bytes = huffman_archive();
+    let offset = HEADER_SIZE + 173;
+    bytes[offset] = 3;
+    bytes[offset + 1] = 0;
+    let archive = Archive::from_bytes(bytes).unwrap();
+    match archive.entry("text.txt").unwrap().decompress(&mut sink()) {
+        Err(Error::HuffmanTree { entry, block_index, offset: error_offset }) =>
+            assert_eq!(("text.txt".to_string(), 0, offset), (entry, block_index, error_offset)),
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn archive_reader() {
+    let name = "tests/twic1134";
+    let mut archive = ArchiveReader::new(File::open(format!("{}.cbv", name)).unwrap()).unwrap();
+    assert_eq!(13, archive.file_list().len());
+    assert_eq!(13, archive.header().file_count());
+
+    // Every other entry is skipped without being read.
+    let mut index = 0;
+    while let Some(mut entry) = archive.next_entry().unwrap() {
+        if index % 2 == 0 {
+            let mut expected = vec![];
+            File::open(format!("{}/{}", name, entry.filename)).unwrap()
+                .read_to_end(&mut expected).unwrap();
+            let mut actual = vec![];
+            entry.decompress(&mut actual).unwrap();
+            assert!(expected == actual);
+        }
+        index += 1;
+    }
+    assert_eq!(13, index);
+
+    for jobs in 1 .. 4 {
+        let temp_dir = TempDir::new();
+        let mut archive = ArchiveReader::new(File::open(format!("{}.cbv", name)).unwrap()).unwrap();
+        let paths: Vec<_> = archive.file_list().iter()
+            .map(|file| Some(temp_dir.path.join(&file.filename)))
+            .collect();
+        archive.extract_to(&paths, jobs).unwrap();
+        for file in get_file_recursives(name) {
+            assert_file(format!("{}/{}", name, file), format!("{}/{}", temp_dir.as_str(), file));
+        }
+    }
+
+    let mut bytes = vec![];
+    File::open("tests/twic1134.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    let mut archive = ArchiveReader::new(&bytes[..200_000]).unwrap();
+    let result = loop {
+        match archive.next_entry() {
+            Ok(Some(mut entry)) => {
+                if let Err(error) = entry.decompress(&mut sink()) {
+                    break error;
+                }
+            },
+            Ok(None) => panic!("The truncated archive was read completely"),
+            Err(error) => break error,
+        }
+    };
+    match result {
+        Error::TruncatedBlock { entry, block_index, offset } =>
+            assert_eq!(("twic1134.cbg", 1, 191227), (entry.as_str(), block_index, offset)),
+        error => panic!("Unexpected error {:?}", error),
+    }
+
+    assert!(matches!(ArchiveReader::new(&[0u8; 16][..]), Err(Error::BadMagic)));
+}
+
+#[test]
+fn archive_writer() {
+    let mut random_bytes = vec![];
+    for _ in 0 .. 100_000 {
+        random_bytes.push(random::<u8>() % 16);
+    }
+    let mut repeated_bytes = vec![];
+    for i in 0 .. 200_000 {
+        repeated_bytes.push((i / 5000 % 3) as u8);
+    }
+    let files = vec![
+        ("empty", vec![]),
+        ("random", random_bytes),
+        ("directory/repeated", repeated_bytes),
+    ];
+
+    for &huffman in &[true, false] {
+        let mut writer = ArchiveWriter::new(vec![]);
+        writer.set_huffman(huffman);
+        for &(filename, ref content) in &files {
+            writer.add(filename, content.as_slice()).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let archive = Archive::from_bytes(bytes).unwrap();
+        assert_eq!(files.len(), archive.len());
+        for (entry, &(filename, ref content)) in archive.entries().zip(&files) {
+            assert_eq!(filename, entry.filename);
+            assert_eq!(content.len(), entry.decompressed_size as usize);
+            let mut actual = vec![];
+            entry.decompress(&mut actual).unwrap();
+            assert!(*content == actual);
+        }
+    }
+}
+
 #[test]
 #[cfg(not(feature = "legacy"))]
 fn ask_confirm() {
@@ -24,6 +235,7 @@ fn ask_confirm() {
             process.args(&["extract", &format!("{}.cbv", name), "-o", dir_name])
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped()) // NOTE: hide the message.
+                .stderr(Stdio::null()) // NOTE: hide the files which were not overwritten.
                 .spawn()
                 .unwrap();
         writeln!(child.stdin.as_mut().unwrap(), "{}", answer).unwrap();
@@ -64,9 +276,10 @@ fn ask_confirm_encrypted() {
         let name = format!("tests/{}", filename);
         let mut process = Command::new(uncbv_executable());
         let mut child =
-            process.args(&["extract", &format!("{}.cbz", name), "-o", dir_name])
+            process.args(&["extract", &format!("{}.cbz", name), "-o", dir_name, "--keep-decrypted"])
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped()) // NOTE: hide the message.
+                .stderr(Stdio::null()) // NOTE: hide the files which were not overwritten.
                 .spawn()
                 .unwrap();
         let mut answers = answers.iter();
@@ -126,6 +339,89 @@ fn ask_confirm_encrypted() {
     assert_eq!(archive_modified_time4, archive_modified_time5);
 }
 
+#[test]
+fn cat_files() {
+    let mut expected = vec![];
+    for file in &["twic1134.cbh", "twic1134.ini"] {
+        File::open(format!("tests/twic1134/{}", file)).unwrap()
+            .read_to_end(&mut expected).unwrap();
+    }
+    let mut process = Command::new(uncbv_executable());
+    let output = process.args(["cat", "tests/twic1134.cbv", "*.cbh", "*.ini"]).output().unwrap();
+    assert!(expected == output.stdout);
+
+    let mut expected = vec![];
+    File::open("tests/twic1134/twic1134.cbg").unwrap()
+        .read_to_end(&mut expected).unwrap();
+    let mut process = Command::new(uncbv_executable());
+    let output = process.args(["extract", "-p", "tests/twic1134.cbv", "twic1134.cbg"]).output().unwrap();
+    assert!(expected == output.stdout);
+
+    let archive = Archive::open("tests/decrypted_small.cbv").unwrap();
+    let entry = archive.entries().next().unwrap();
+    let mut expected = vec![];
+    entry.decompress(&mut expected).unwrap();
+    let mut process = Command::new(uncbv_executable());
+    let mut child =
+        process.args(["cat", "tests/small.cbz", &entry.filename])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()) // NOTE: hide the password prompt.
+            .spawn()
+            .unwrap();
+    writeln!(child.stdin.as_mut().unwrap(), "{}", DEFAULT_PASSWORD).unwrap();
+    assert!(expected == child.wait_with_output().unwrap().stdout);
+}
+
+#[test]
+fn create_archive() {
+    for filename in &["small", "twic1134"] {
+        let temp_dir = TempDir::new();
+        let archive = format!("{}/{}.cbv", temp_dir.as_str(), filename);
+        let name = format!("tests/{}", filename);
+        Command::new(uncbv_executable())
+            .args(["create", &archive, &name])
+            .status()
+            .unwrap();
+
+        let dir_name = format!("{}/output", temp_dir.as_str());
+        create_dir_all(&dir_name).unwrap();
+        Command::new(uncbv_executable())
+            .args(["extract", &archive, "-o", &dir_name])
+            .status()
+            .unwrap();
+
+        let expected_files = get_file_recursives(&name);
+        assert!(expected_files.len() > 1);
+        assert_eq!(expected_files, get_file_recursives(&dir_name));
+
+        for file in expected_files {
+            assert_file(format!("{}/{}", name, file), format!("{}/{}", dir_name, file));
+        }
+    }
+
+    // The archive is not added to itself, even when it already exists.
+    let temp_dir = TempDir::new();
+    let input_dir = format!("{}/input", temp_dir.as_str());
+    create_dir_all(&input_dir).unwrap();
+    File::create(format!("{}/file.txt", input_dir)).unwrap().write_all(b"content").unwrap();
+    let archive = format!("{}/self.cbv", input_dir);
+    for _ in 0 .. 2 {
+        assert_eq!(Some(0), uncbv_exit_code(&["create", &archive, &input_dir, "--no-confirm"]));
+        assert_eq!("file.txt\n", run_uncbv(&["list", &archive]));
+    }
+
+    // A failed creation does not replace the existing archive.
+    let mut content = vec![];
+    File::open(&archive).unwrap().read_to_end(&mut content).unwrap();
+    let missing = format!("{}/missing.txt", temp_dir.as_str());
+    assert_eq!(Some(3), uncbv_exit_code(&["create", &archive, &input_dir, &missing, "--no-confirm"]));
+    let mut new_content = vec![];
+    File::open(&archive).unwrap().read_to_end(&mut new_content).unwrap();
+    assert_eq!(content, new_content);
+    assert_eq!(vec!["file.txt", "self.cbv"], get_file_recursives(&input_dir));
+}
+
 #[test]
 fn create_dir_argument() {
     let temp_dir = TempDir::new();
@@ -221,7 +517,7 @@ fn dont_ask_confirm_encrypted() {
         let name = format!("tests/{}", filename);
         let mut process = Command::new(uncbv_executable());
         let mut child =
-            process.args(&["extract", &format!("{}.cbz", name), "-o", dir_name, "--no-confirm"])
+            process.args(&["extract", &format!("{}.cbz", name), "-o", dir_name, "--no-confirm", "--keep-decrypted"])
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped()) // NOTE: hide the message.
                 .spawn()
@@ -254,6 +550,197 @@ fn dont_ask_confirm_encrypted() {
     assert!(archive_modified_time1 != archive_modified_time2);
 }
 
+#[test]
+fn encrypt_files() {
+    fn encrypt(filename: &str, password: &str, output_file: &str) {
+        assert_eq!(Some(0), encrypt_confirmed(filename, password, password, output_file));
+    }
+
+    fn encrypt_confirmed(filename: &str, password: &str, confirmation: &str, output_file: &str) -> Option<i32> {
+        let mut process = Command::new(uncbv_executable());
+        let mut child =
+            process.args(["encrypt", filename, "-o", output_file])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped()) // NOTE: hide the password prompt.
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+        writeln!(child.stdin.as_mut().unwrap(), "{}\n{}", password, confirmation).unwrap();
+        child.wait_with_output().unwrap().status.code()
+    }
+
+    let temp_dir = TempDir::new();
+
+    let output_file = format!("{}/small.cbz", temp_dir.as_str());
+    encrypt("tests/decrypted_small.cbv", DEFAULT_PASSWORD, &output_file);
+    assert_file("tests/small.cbz".to_string(), output_file);
+
+    // The archive is not written when the confirmation differs from the password.
+    let mismatch_file = format!("{}/mismatch.cbz", temp_dir.as_str());
+    assert_eq!(Some(2), encrypt_confirmed("tests/decrypted_small.cbv", DEFAULT_PASSWORD, "passwrod", &mismatch_file));
+    assert!(!PathBuf::from(&mismatch_file).exists());
+
+    // The encrypted archive cannot replace the archive to encrypt.
+    let input_file = format!("{}/input.cbv", temp_dir.as_str());
+    copy("tests/decrypted_small.cbv", &input_file).unwrap();
+    let args = ["encrypt", &input_file, "-o", &input_file, "--no-confirm", "--password", DEFAULT_PASSWORD];
+    assert_eq!(Some(2), uncbv_exit_code(&args));
+    assert!(run_uncbv_stderr(&args).contains("which is the archive to encrypt"));
+    assert_file("tests/decrypted_small.cbv".to_string(), input_file);
+
+    let password = "another password";
+    let output_file = format!("{}/twic1134.cbz", temp_dir.as_str());
+    encrypt("tests/twic1134.cbv", password, &output_file);
+
+    let dir_name = format!("{}/twic1134", temp_dir.as_str());
+    create_dir_all(&dir_name).unwrap();
+    let mut child =
+        Command::new(uncbv_executable())
+            .args(["extract", &output_file, "-o", &dir_name])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped()) // NOTE: hide the password prompt.
+            .spawn()
+            .unwrap();
+    writeln!(child.stdin.as_mut().unwrap(), "{}", password).unwrap();
+    child.wait().unwrap();
+
+    let expected_files = get_file_recursives("tests/twic1134");
+    for file in expected_files {
+        assert_file(format!("tests/twic1134/{}", file), format!("{}/{}", dir_name, file));
+    }
+}
+
+#[test]
+fn entry_reader() {
+    let name = "tests/twic1134";
+    let archive = Archive::open(format!("{}.cbv", name)).unwrap();
+    let entry = archive.entries().find(|entry| entry.filename == "twic1134.cbg").unwrap();
+
+    let mut expected = vec![];
+    File::open(format!("{}/{}", name, entry.filename)).unwrap()
+        .read_to_end(&mut expected).unwrap();
+
+    let mut reader = entry.reader();
+    let mut actual = vec![];
+    let mut buffer = [0; 1000];
+    loop {
+        let size = reader.read(&mut buffer).unwrap();
+        if size == 0 {
+            break;
+        }
+        actual.extend_from_slice(&buffer[..size]);
+    }
+    assert!(expected == actual);
+}
+
+#[test]
+#[cfg(not(feature = "legacy"))]
+fn exit_codes() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let not_archive = format!("{}/not_archive.cbv", dir_name);
+    File::create(&not_archive).unwrap().write_all(b"Not an archive").unwrap();
+    let truncated = format!("{}/truncated.cbv", dir_name);
+    let mut bytes = vec![];
+    File::open("tests/twic1134.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    File::create(&truncated).unwrap().write_all(&bytes[..200_000]).unwrap();
+    let password_list = format!("{}/passwords", dir_name);
+    writeln!(File::create(&password_list).unwrap(), "foo").unwrap();
+
+    assert_eq!(Some(0), uncbv_exit_code(&["list", "tests/small.cbv"]));
+    assert_eq!(Some(0), uncbv_exit_code(&["--help"]));
+
+    assert_eq!(Some(2), uncbv_exit_code(&["list"]));
+    assert_eq!(Some(2), uncbv_exit_code(&["list", "tests/small.cbv", "--filename-encoding", "unknown"]));
+    assert_eq!(Some(2), uncbv_exit_code(&["extract", "tests/small.cbv", "*.zip", "-o", dir_name]));
+    // NOTE: stdin is empty, so no password is given.
+    assert_eq!(Some(2), uncbv_exit_code(&["list", "tests/small.cbz"]));
+
+    assert_eq!(Some(3), uncbv_exit_code(&["list", "tests/missing.cbv"]));
+
+    assert_eq!(Some(4), uncbv_exit_code(&["list", &not_archive]));
+    assert_eq!(Some(4), uncbv_exit_code(&["list", &not_archive, "--format", "json"]));
+    assert_eq!(Some(4), uncbv_exit_code(&["test", &truncated]));
+    assert_eq!(Some(4), uncbv_exit_code(&["extract", &truncated, "-o", dir_name]));
+    let empty = format!("{}/empty.cbv", dir_name);
+    File::create(&empty).unwrap();
+    assert_eq!(Some(4), uncbv_exit_code(&["list", &empty, "--verbose"]));
+
+    assert_eq!(Some(5), uncbv_exit_code(&["list", "tests/small.cbz", "--password", "wrong"]));
+    assert_eq!(Some(5), uncbv_exit_code(&["extract", "tests/small.cbz", "-o", dir_name, "--password", "wrong"]));
+    assert_eq!(Some(5), uncbv_exit_code(&["list", "tests/small.cbz", "--password-list", &password_list]));
+
+    // NOTE: without any answer on stdin, the existing files are not overwritten.
+    let output_dir = format!("{}/output", dir_name);
+    create_dir_all(&output_dir).unwrap();
+    assert_eq!(Some(0), uncbv_exit_code(&["extract", "tests/small.cbv", "-o", &output_dir]));
+    assert_eq!(Some(6), uncbv_exit_code(&["extract", "tests/small.cbv", "-o", &output_dir]));
+    assert_eq!(Some(0), uncbv_exit_code(&["extract", "tests/small.cbv", "-o", &output_dir, "--skip-existing"]));
+    assert_eq!(Some(6), uncbv_exit_code(&["create", &not_archive, "tests/small"]));
+    let output = run_uncbv_stderr(&["create", &not_archive, "tests/small"]);
+    assert_eq!(format!("{0}: The file {0} already exists and was not overwritten\n", not_archive), output);
+}
+
+#[test]
+#[cfg(not(feature = "legacy"))]
+fn extract_encrypted_in_memory() {
+    fn try_extract(filename: &str, password: &str, dir_name: &str, answer: Option<&str>) {
+        let name = format!("tests/{}", filename);
+        let mut process = Command::new(uncbv_executable());
+        let mut child =
+            process.args(&["extract", &format!("{}.cbz", name), "-o", dir_name])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped()) // NOTE: hide the message.
+                .stderr(Stdio::null()) // NOTE: hide the files which were not overwritten.
+                .spawn()
+                .unwrap();
+        writeln!(child.stdin.as_mut().unwrap(), "{}", password).unwrap();
+        if let Some(answer) = answer {
+            writeln!(child.stdin.as_mut().unwrap(), "{}", answer).unwrap();
+        }
+        child.wait().unwrap();
+
+        let expected_files = get_file_recursives(&name);
+        assert_eq!(expected_files, get_file_recursives(dir_name));
+
+        for file in expected_files {
+            assert_file(format!("{}/{}", name, file), format!("{}/{}", dir_name, file));
+        }
+    }
+
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let filename = "small";
+    try_extract(filename, "password", dir_name, None);
+
+    let modified_time1 = metadata(format!("{}/{}.cbh", dir_name, filename)).unwrap().modified().unwrap();
+
+    try_extract(filename, "password", dir_name, Some("no"));
+
+    let modified_time2 = metadata(format!("{}/{}.cbh", dir_name, filename)).unwrap().modified().unwrap();
+    assert_eq!(modified_time1, modified_time2);
+
+    try_extract(filename, "password", dir_name, Some("yes"));
+
+    let modified_time3 = metadata(format!("{}/{}.cbh", dir_name, filename)).unwrap().modified().unwrap();
+    assert!(modified_time1 != modified_time3);
+
+    let archive = Archive::open_encrypted("tests/small.cbz", DEFAULT_PASSWORD).unwrap();
+    assert_eq!(12, archive.len());
+
+    // A corrupted archive decrypted with the right password is not reported as a wrong password.
+    let mut bytes = vec![];
+    File::open("tests/decrypted_small.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    let corrupted = format!("{}/truncated.cbz", dir_name);
+    encrypt(&bytes[.. HEADER_SIZE + 16], DEFAULT_PASSWORD, &mut File::create(&corrupted).unwrap()).unwrap();
+    assert!(matches!(Archive::open_encrypted(&corrupted, DEFAULT_PASSWORD), Err(Error::TruncatedFileList)));
+    assert!(matches!(Archive::open_encrypted(&corrupted, "wrong"), Err(Error::WrongPassword)));
+    assert_eq!(format!("{}: Truncated file list\n", corrupted),
+        run_uncbv_stderr(&["list", &corrupted, "--password", DEFAULT_PASSWORD]));
+    assert_eq!(Some(4), uncbv_exit_code(&["list", &corrupted, "--password", DEFAULT_PASSWORD]));
+    assert_eq!(Some(5), uncbv_exit_code(&["list", &corrupted, "--password", "wrong"]));
+}
+
 #[test]
 fn extract_files() {
     extract("twic1134");
@@ -274,6 +761,362 @@ fn extract_files2() {
     }
 }
 
+#[test]
+fn extract_from_stdin() {
+    for filename in &["small", "twic1134"] {
+        for jobs in &["1", "4"] {
+            let temp_dir = TempDir::new();
+            let dir_name = temp_dir.as_str();
+            let name = format!("tests/{}", filename);
+            let status = Command::new(uncbv_executable())
+                .args(["extract", "-", "-o", dir_name, "--jobs", jobs])
+                .stdin(File::open(format!("{}.cbv", name)).unwrap())
+                .status()
+                .unwrap();
+            assert!(status.success());
+
+            for file in get_file_recursives(&name) {
+                assert_file(format!("{}/{}", name, file), format!("{}/{}", dir_name, file));
+            }
+        }
+    }
+
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let status = Command::new(uncbv_executable())
+        .args(["extract", "-", "-o", dir_name, "--password", DEFAULT_PASSWORD])
+        .stdin(File::open("tests/small.cbz").unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    for file in get_file_recursives("tests/small") {
+        assert_file(format!("tests/small/{}", file), format!("{}/{}", dir_name, file));
+    }
+
+    // NOTE: the password cannot be asked since stdin contains the archive, so an encrypted archive
+    // cannot be recognized without it.
+    let output = Command::new(uncbv_executable())
+        .args(["list", "-"])
+        .stdin(File::open("tests/small.cbz").unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(Some(4), output.status.code());
+    assert_eq!("-: Not a CBV archive\n", String::from_utf8(output.stderr).unwrap());
+
+    let output = Command::new(uncbv_executable())
+        .args(["cat", "-", "*.ini"])
+        .stdin(File::open("tests/twic1134.cbv").unwrap())
+        .output()
+        .unwrap();
+    let mut expected = vec![];
+    File::open("tests/twic1134/twic1134.ini").unwrap().read_to_end(&mut expected).unwrap();
+    assert!(expected == output.stdout);
+}
+
+#[test]
+fn extract_over_directory() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let target = format!("{}/small.cbh", dir_name);
+    create_dir_all(&target).unwrap();
+
+    let output = Command::new(uncbv_executable())
+        .args(["extract", "--overwrite", "tests/small.cbv", "-o", dir_name])
+        .output()
+        .unwrap();
+    assert_eq!(Some(3), output.status.code());
+    // The error names the file which cannot be written.
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&target));
+
+    let temporary_files: Vec<_> = WalkDir::new(dir_name).into_iter()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|filename| filename.ends_with(".part"))
+        .collect();
+    assert!(temporary_files.is_empty(), "Temporary files left: {:?}", temporary_files);
+
+    // The decrypted archive is also written in a temporary file.
+    let target = format!("{}/small.cbv", dir_name);
+    create_dir_all(&target).unwrap();
+    let output = Command::new(uncbv_executable())
+        .args(["extract", "--overwrite", "--keep-decrypted", "tests/small.cbz", "-o", dir_name, "--password",
+            DEFAULT_PASSWORD])
+        .output()
+        .unwrap();
+    assert_eq!(Some(3), output.status.code());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&target));
+
+    let temporary_files: Vec<_> = WalkDir::new(dir_name).into_iter()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|filename| filename.ends_with(".part"))
+        .collect();
+    assert!(temporary_files.is_empty(), "Temporary files left: {:?}", temporary_files);
+}
+
+#[test]
+fn extract_parallel() {
+    for filename in &["small", "twic1134"] {
+        let temp_dir = TempDir::new();
+        let dir_name = temp_dir.as_str();
+        let name = format!("tests/{}", filename);
+        let mut process = Command::new(uncbv_executable());
+        let status = process.args(["extract", &format!("{}.cbv", name), "-o", dir_name, "--jobs", "4"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let expected_files = get_file_recursives(&name);
+        assert!(expected_files.len() > 1);
+        for file in expected_files {
+            assert_file(format!("{}/{}", name, file), format!("{}/{}", dir_name, file));
+        }
+    }
+
+    let temp_dir = TempDir::new();
+    let archive = Archive::open("tests/twic1134.cbv").unwrap();
+    archive.extract_parallel(temp_dir.as_str(), 3).unwrap();
+    for file in get_file_recursives("tests/twic1134") {
+        assert_file(format!("tests/twic1134/{}", file), format!("{}/{}", temp_dir.as_str(), file));
+    }
+
+    // The number of threads is limited.
+    let temp_dir = TempDir::new();
+    assert_eq!(Some(0), uncbv_exit_code(&["extract", "tests/twic1134.cbv", "-o", temp_dir.as_str(), "--jobs",
+        "200000"]));
+    for file in get_file_recursives("tests/twic1134") {
+        assert_file(format!("tests/twic1134/{}", file), format!("{}/{}", temp_dir.as_str(), file));
+    }
+}
+
+#[test]
+fn extract_selected() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let mut process = Command::new(uncbv_executable());
+    process.args(["extract", "tests/twic1134.cbv", "*.cbh", "*.cbt*", "--exclude", "*.cbtt", "-o", dir_name])
+        .status()
+        .unwrap();
+    assert_eq!(vec!["twic1134.cbh", "twic1134.cbt"], get_file_recursives(dir_name));
+    for file in get_file_recursives(dir_name) {
+        assert_file(format!("tests/twic1134/{}", file), format!("{}/{}", dir_name, file));
+    }
+
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let mut process = Command::new(uncbv_executable());
+    let output = process.args(["extract", "tests/twic1134.cbv", "*.cbh", "*.zip", "-o", dir_name])
+        .output()
+        .unwrap();
+    let output = String::from_utf8(output.stderr).unwrap();
+    assert!(output.contains("No file matches the pattern *.zip"));
+    assert!(get_file_recursives(dir_name).is_empty());
+}
+
+#[test]
+fn extract_truncated_archive() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let mut bytes = vec![];
+    File::open("tests/twic1134.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    // NOTE: the archive ends in the middle of twic1134.cbg.
+    let archive_file = format!("{}/truncated.cbv", dir_name);
+    File::create(&archive_file).unwrap().write_all(&bytes[..200_000]).unwrap();
+
+    for jobs in &["1", "4"] {
+        let output_dir = format!("{}/output{}", dir_name, jobs);
+        create_dir_all(&output_dir).unwrap();
+        let output = run_uncbv_stderr(&["extract", &archive_file, "-o", &output_dir, "--jobs", jobs]);
+        assert_eq!(format!("{}: Truncated block 1 of twic1134.cbg at offset 191227\n", archive_file), output);
+
+        // Only complete files are extracted, without any temporary file left.
+        let files: Vec<_> = read_dir(&output_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(files.iter().all(|file| file == "twic1134.cbh" || file == "twic1134.ini"));
+        for file in &files {
+            assert_file(format!("tests/twic1134/{}", file), format!("{}/{}", output_dir, file));
+        }
+    }
+
+    // The context of the error is shown in separate fields.
+    let output = run_uncbv(&["list", "-v", &archive_file, "--format", "json"]);
+    let error: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!("TruncatedBlock", error["kind"]);
+    assert_eq!("twic1134.cbg", error["entry"]);
+    assert_eq!(1, error["block_index"]);
+    assert_eq!(191227, error["offset"]);
+    let output = run_uncbv(&["list", "-v", &archive_file, "--format", "csv"]);
+    assert_eq!(format!("filename,error,kind,entry,block_index,offset,actual,expected,reason\n\
+        {},Truncated block 1 of twic1134.cbg at offset 191227,TruncatedBlock,twic1134.cbg,1,191227,,,\n",
+        archive_file), output);
+}
+
+#[test]
+fn extract_unsafe_filenames() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let output = run_uncbv_stderr(&["extract", "tests/traversal.cbv", "-o", dir_name, "--no-confirm"]);
+    assert_eq!("tests/traversal.cbv: Unsafe filename \"../../evil.txt\": parent directory
+tests/traversal.cbv: Unsafe filename \"/tmp/evil.txt\": absolute path
+tests/traversal.cbv: Unsafe filename \"C:/evil.txt\": drive letter
+tests/traversal.cbv: Unsafe filename \"sub/./../../evil.txt\": parent directory
+tests/traversal.cbv: 4 files were not extracted because of an unsafe filename
+", output);
+    assert_eq!(vec!["good.txt", "sub/nested.txt"], get_file_recursives(dir_name));
+    assert_eq!(Some(4), uncbv_exit_code(&["extract", "tests/traversal.cbv", "-o", dir_name, "--no-confirm"]));
+
+    let archive = Archive::open("tests/traversal.cbv").unwrap();
+    let output_dir = PathBuf::from(dir_name);
+    let entry = archive.entry("./sub//nested.txt").unwrap();
+    assert_eq!(output_dir.join("sub/nested.txt"), entry.output_path(dir_name).unwrap());
+    assert!(archive.entry("../../evil.txt").unwrap().output_path(dir_name).is_err());
+    assert!(archive.extract(dir_name).is_err());
+}
+
+#[test]
+fn filename_encodings() {
+    let encodings = [
+        ("cp1252", "àéñª.txt"),
+        ("cp1251", "айсЄ.txt"),
+        ("cp437", "αΘ±¬.txt"),
+        // NOTE: 0xAA is not defined in the code page 1253.
+        ("cp1253", "ΰιρ\u{FFFD}.txt"),
+    ];
+    for &(encoding, filename) in &encodings {
+        let output = run_uncbv(&["list", "tests/encoding.cbv", "--filename-encoding", encoding]);
+        assert_eq!(format!("{}\n", filename), output);
+    }
+
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    run_uncbv(&["extract", "tests/encoding.cbv", "-o", dir_name, "--filename-encoding", "cp1251"]);
+    assert_eq!(vec!["айсЄ.txt"], get_file_recursives(dir_name));
+
+    let mut archive = Archive::open("tests/encoding.cbv").unwrap();
+    archive.set_filename_encoding(filename_encoding("cp437").unwrap()).unwrap();
+    assert!(archive.entry("αΘ±¬.txt").is_some());
+    assert!(filename_encoding("unknown").is_none());
+}
+
+#[test]
+fn identify_files() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let encrypted = format!("{}/renamed.dat", dir_name);
+    let plain = format!("{}/plain.cbz", dir_name);
+    let junk = format!("{}/junk.cbv", dir_name);
+    // NOTE: the size of this file is a multiple of the DES block size, like an encrypted archive.
+    let block_sized_junk = format!("{}/junk16.cbv", dir_name);
+    // NOTE: this file has the size of an encrypted archive, but starts with the CBV magic number.
+    let corrupted = format!("{}/corrupted.cbz", dir_name);
+    copy("tests/small.cbz", &encrypted).unwrap();
+    copy("tests/small.cbv", &plain).unwrap();
+    File::create(&junk).unwrap().write_all(b"not an archive").unwrap();
+    File::create(&block_sized_junk).unwrap().write_all(b"not an archive!!").unwrap();
+    File::create(&corrupted).unwrap().write_all(b"\x08\x00not an archive").unwrap();
+
+    // The archives are recognized by their content, whatever their extension is. Without the password,
+    // an encrypted archive cannot be distinguished from a file having the size of an encrypted archive.
+    let output = run_uncbv(&["identify", "tests/small.cbv", "tests/small.cbz", &encrypted, &plain, &junk,
+        &block_sized_junk, &corrupted]);
+    assert_eq!(format!("tests/small.cbv: plain\ntests/small.cbz: possibly-encrypted\n{}: possibly-encrypted\n\
+        {}: plain\n{}: unknown\n{}: possibly-encrypted\n{}: unknown\n", encrypted, plain, junk, block_sized_junk,
+        corrupted), output);
+    assert_eq!(Some(1), uncbv_exit_code(&["identify", "tests/small.cbv", &junk]));
+    assert_eq!(Some(0), uncbv_exit_code(&["identify", "tests/small.cbv", "tests/small.cbz", &encrypted, &plain]));
+
+    // With the password, the encrypted archives are recognized.
+    let output = run_uncbv(&["identify", &encrypted, &block_sized_junk, &plain, "--password", DEFAULT_PASSWORD]);
+    assert_eq!(format!("{}: encrypted\n{}: unknown\n{}: plain\n", encrypted, block_sized_junk, plain), output);
+    assert_eq!(Some(1), uncbv_exit_code(&["identify", &block_sized_junk, "--password", DEFAULT_PASSWORD]));
+    let password_list = format!("{}/passwords", dir_name);
+    writeln!(File::create(&password_list).unwrap(), "wrong\n{}", DEFAULT_PASSWORD).unwrap();
+    assert_eq!(format!("{}: encrypted\n", encrypted),
+        run_uncbv(&["identify", &encrypted, "--password-list", &password_list]));
+
+    // A file having the size of an encrypted archive which the password does not decrypt is not
+    // reported as only a wrong password.
+    let output = run_uncbv_stderr(&["list", &block_sized_junk, "--password", DEFAULT_PASSWORD]);
+    assert_eq!(format!("{}: Wrong password, or not an encrypted CBV archive\n", block_sized_junk), output);
+
+    // The errors are shown on stderr.
+    let missing = format!("{}/missing.cbv", dir_name);
+    let output = run_uncbv_stderr(&["identify", "tests/small.cbv", &missing]);
+    assert!(output.starts_with(&format!("{}: ", missing)));
+    assert_eq!("tests/small.cbv: plain\n", run_uncbv(&["identify", "tests/small.cbv", &missing]));
+
+    // The password is not asked for an unknown file.
+    for args in &[vec!["list", &junk], vec!["list", &junk, "--password", DEFAULT_PASSWORD]] {
+        let output = Command::new(uncbv_executable())
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert_eq!(Some(4), output.status.code());
+        assert!(!String::from_utf8(output.stdout).unwrap().contains("Password"));
+        assert_eq!(format!("{}: Not a CBV archive\n", junk), String::from_utf8(output.stderr).unwrap());
+    }
+
+    // The password of the renamed encrypted archive is asked.
+    let mut child = Command::new(uncbv_executable())
+        .args(["list", &encrypted])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(child.stdin.as_mut().unwrap(), "{}", DEFAULT_PASSWORD).unwrap();
+    let output = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    assert!(output.starts_with("Password:\n"));
+    assert_eq!(get_file_recursives("tests/small").len() + 1, output.lines().count());
+
+    // The renamed archives are handled according to their content.
+    let output_dir = format!("{}/output", dir_name);
+    create_dir_all(&output_dir).unwrap();
+    run_uncbv(&["extract", &encrypted, "-o", &output_dir, "--password", DEFAULT_PASSWORD]);
+    assert_eq!(get_file_recursives("tests/small"), get_file_recursives(&output_dir));
+
+    let output = run_uncbv(&["list", &plain]);
+    assert!(!output.contains("Password"));
+    assert_eq!(get_file_recursives("tests/small").len(), output.lines().count());
+
+    // The decrypted archive cannot replace the encrypted archive.
+    let output = run_uncbv_stderr(&["decrypt", &encrypted, "-o", &encrypted, "--password", DEFAULT_PASSWORD,
+        "--no-confirm"]);
+    assert!(output.contains("which is the encrypted archive"));
+    let output = run_uncbv(&["info", &encrypted, "--password", DEFAULT_PASSWORD]);
+    assert!(output.contains("Encrypted:         yes"));
+}
+
+#[test]
+fn keep_newer_close_times() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let archived_time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let source = format!("{}/source.txt", dir_name);
+    File::create(&source).unwrap().write_all(b"archived").unwrap();
+    File::options().write(true).open(&source).unwrap().set_modified(archived_time).unwrap();
+    let archive = format!("{}/archive.cbv", dir_name);
+    let mut writer = ArchiveWriter::new(File::create(&archive).unwrap());
+    writer.add_path("file.txt", &source).unwrap();
+    writer.finish().unwrap();
+
+    // NOTE: the times of the archive are in UTC, like the times of the filesystem.
+    let output_dir = format!("{}/output", dir_name);
+    create_dir_all(&output_dir).unwrap();
+    let existing = format!("{}/file.txt", output_dir);
+    for &(existing_time, kept) in &[
+        (archived_time + Duration::from_secs(1), true),
+        (archived_time, true),
+        (archived_time - Duration::from_secs(1), false),
+    ] {
+        File::create(&existing).unwrap().write_all(b"existing").unwrap();
+        File::options().write(true).open(&existing).unwrap().set_modified(existing_time).unwrap();
+        run_uncbv(&["extract", &archive, "-o", &output_dir, "--keep-newer"]);
+        let mut content = String::new();
+        File::open(&existing).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(if kept { "existing" } else { "archived" }, content);
+    }
+}
+
 #[test]
 fn list_files() {
     list("twic1134");
@@ -294,6 +1137,428 @@ fn list_files2() {
     }
 }
 
+#[test]
+fn list_verbose() {
+    let mut process = Command::new(uncbv_executable());
+    let output = process.args(["list", "-v", "tests/twic1134.cbv"]).output().unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<Vec<&str>> = output.lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(15, lines.len());
+    assert_eq!(vec!["Compressed", "Decompressed", "Ratio", "Blocks", "Huffman", "LZ", "Name"], lines[0]);
+    assert!(lines.contains(&vec!["127571", "281428", "45.3%", "5", "5", "5", "twic1134.cbh"]));
+    assert!(lines.contains(&vec!["31", "26", "119.2%", "1", "0", "0", "twic1134.cba"]));
+    assert_eq!(vec!["803122", "1749596", "45.9%", "38", "31", "26", "13", "files"], lines[14]);
+}
+
+#[test]
+fn lz_blocks() {
+    let abc_18: Vec<u8> = b"abc".iter().cycle().take(18).cloned().collect();
+    let abc_51: Vec<u8> = b"abc".iter().cycle().take(51).cloned().collect();
+    let alphabet = b"abcdefghijklmnopqrst";
+    let mut alphabet_twice = alphabet.to_vec();
+    alphabet_twice.extend_from_slice(alphabet);
+    let mut alphabet_block = vec![0x00, 0x00];
+    alphabet_block.extend_from_slice(&alphabet[..16]);
+    alphabet_block.extend_from_slice(&[0x00, 0x08]);
+    alphabet_block.extend_from_slice(&alphabet[16..]);
+    alphabet_block.extend_from_slice(&[0x21, 0x01, 0x04]);
+
+    // Every item is announced by a bit of the control word (most significant bit first): 0 for a
+    // literal, 1 for a run or a backward reference.
+    let blocks: Vec<(&str, Vec<u8>, Vec<u8>)> = vec![
+        ("literals", vec![0x00, 0x00, b'a', b'b', b'c'], b"abc".to_vec()),
+        ("short run", vec![0x00, 0x80, 0x02, b'x'], vec![b'x'; 5]),
+        ("long run", vec![0x00, 0x80, 0x15, 0x02, b'y'], vec![b'y'; 5 + (2 << 4) + 0x13]),
+        ("short reference", vec![0x00, 0x02, b'a', b'b', b'c', b'd', b'e', b'f', 0x33, 0x00], b"abcdefabc".to_vec()),
+        ("overlapping short reference", vec![0x00, 0x10, b'a', b'b', b'c', 0xF0, 0x00], abc_18),
+        ("long reference", alphabet_block, alphabet_twice),
+        ("overlapping long reference", vec![0x00, 0x10, b'a', b'b', b'c', 0x20, 0x00, 0x20], abc_51),
+        ("reference to a run", vec![0x00, 0xC0, 0x00, b'z', 0x40, 0x00], vec![b'z'; 7]),
+    ];
+    for (name, block, expected) in blocks {
+        let archive = Archive::from_bytes(archive_with_block(&block, expected.len())).unwrap();
+        let entry = archive.entry("block.bin").unwrap();
+        let mut actual = vec![];
+        entry.decompress(&mut actual).unwrap_or_else(|error| panic!("{}: {}", name, error));
+        assert!(expected == actual, "{}: {:?}", name, actual);
+        entry.verify().unwrap();
+    }
+
+    // A reference before the beginning of the block is an error.
+    let archive = Archive::from_bytes(archive_with_block(&[0x00, 0x40, b'a', 0x30, 0x00], 4)).unwrap();
+    assert!(archive.entry("block.bin").unwrap().decompress(&mut sink()).is_err());
+
+    // The writer produces overlapping references for repeated patterns.
+    let content: Vec<u8> = b"0123".iter().cycle().take(10_000).cloned().collect();
+    let mut writer = ArchiveWriter::new(vec![]);
+    writer.set_huffman(false);
+    writer.add("pattern", &content[..]).unwrap();
+    let archive = Archive::from_bytes(writer.finish().unwrap()).unwrap();
+    let entry = archive.entry("pattern").unwrap();
+    assert!(entry.compressed_size < 200);
+    let mut actual = vec![];
+    entry.decompress(&mut actual).unwrap();
+    assert!(content == actual);
+}
+
+#[test]
+fn mutated_archives() {
+    /// Run every parser on `bytes`, which must return errors instead of panicking.
+    fn check(bytes: &[u8]) {
+        if let Ok(archive) = Archive::from_bytes(bytes.to_vec()) {
+            for entry in archive.entries() {
+                let _ = entry.block_flags();
+                let _ = entry.verify();
+                let _ = entry.decompress(&mut sink());
+            }
+        }
+
+        // Every other entry is skipped by the streaming reader.
+        if let Ok(mut archive) = ArchiveReader::new(bytes) {
+            let mut index = 0;
+            while let Ok(Some(mut entry)) = archive.next_entry() {
+                if index % 2 == 0 && entry.decompress(&mut sink()).is_err() {
+                    break;
+                }
+                index += 1;
+            }
+        }
+    }
+
+    /// Extract `bytes` with the decompression threads of the streaming reader.
+    fn check_extraction(bytes: &[u8], output_dir: &TempDir) {
+        if let Ok(mut archive) = ArchiveReader::new(bytes) {
+            let paths: Vec<_> = (0 .. archive.file_list().len())
+                .map(|index| Some(output_dir.path.join(index.to_string())))
+                .collect();
+            let _ = archive.extract_to(&paths, 2);
+        }
+    }
+
+    fn mutate(bytes: &[u8]) {
+        let output_dir = TempDir::new();
+        for size in 0 .. bytes.len() {
+            check(&bytes[..size]);
+            check_extraction(&bytes[..size], &output_dir);
+        }
+        let mut mutated = bytes.to_vec();
+        for index in 0 .. bytes.len() {
+            for &value in &[0x00, 0xFF, bytes[index] ^ 0x01, bytes[index] ^ 0x80] {
+                mutated[index] = value;
+                check(&mutated);
+            }
+            mutated[index] = bytes[index];
+        }
+
+        // Mutate several bytes at once, with a deterministic xorshift generator.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0 .. 2000 {
+            let mut mutated = bytes.to_vec();
+            for _ in 0 .. 1 + next() % 8 {
+                let index = (next() % bytes.len() as u64) as usize;
+                mutated[index] = next() as u8;
+            }
+            check(&mutated);
+        }
+    }
+
+    let mut small = vec![];
+    File::open("tests/small.cbv").unwrap().read_to_end(&mut small).unwrap();
+    mutate(&small);
+
+    mutate(&huffman_archive());
+
+    // A truncated encrypted archive, whose size is not a multiple of the DES block size.
+    let mut encrypted = vec![];
+    File::open("tests/small.cbz").unwrap().read_to_end(&mut encrypted).unwrap();
+    for size in (0 .. 64).chain(encrypted.len() - 64 .. encrypted.len()) {
+        let _ = check_password(&encrypted[..size], DEFAULT_PASSWORD);
+        let mut decrypted = vec![];
+        let _ = Decryptor::new(&encrypted[..size], DEFAULT_PASSWORD).read_to_end(&mut decrypted);
+        check(&decrypted);
+    }
+}
+
+#[test]
+fn output_formats() {
+    let output = run_uncbv(&["list", "tests/twic1134.cbv", "--format", "json"]);
+    let list: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(13, list["header"]["file_count"]);
+    assert_eq!(173, list["header"]["record_size"]);
+    let files = list["files"].as_array().unwrap();
+    assert_eq!(13, files.len());
+    assert_eq!("twic1134.cbh", files[0]["filename"]);
+    assert_eq!(127571, files[0]["compressed_size"]);
+    assert_eq!(281428, files[0]["decompressed_size"]);
+    assert_eq!(1470080312, files[0]["modified"]);
+    assert!(files[0].get("block_count").is_none());
+
+    let output = run_uncbv(&["list", "-v", "tests/twic1134.cbv", "--format", "json"]);
+    let list: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(5, list["files"][0]["block_count"]);
+
+    let output = run_uncbv(&["list", "tests/twic1134.cbv", "--format", "csv"]);
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(14, lines.len());
+    assert_eq!("filename,compressed_size,decompressed_size,modified,file_count,record_size", lines[0]);
+    assert_eq!("twic1134.cbh,127571,281428,1470080312,13,173", lines[1]);
+
+    let output = run_uncbv(&["test", "tests/twic1134.cbv", "--format", "json"]);
+    let results: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(true, results["ok"]);
+    assert_eq!(13, results["files"].as_array().unwrap().len());
+    assert_eq!(Value::Null, results["files"][0]["error"]);
+
+    let output = run_uncbv(&["info", "tests/twic1134.cbv", "--format", "csv"]);
+    assert_eq!("encrypted,file_count,record_size,compressed_size,decompressed_size\nfalse,13,173,803122,1749596\n",
+        output);
+
+    let output = run_uncbv(&["info", "tests/missing.cbv", "--format", "json"]);
+    let error: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!("tests/missing.cbv", error["filename"]);
+    assert_eq!("NotFound", error["kind"]);
+
+    // A reader which does not want more data is not an error.
+    for format in &["text", "json", "csv"] {
+        let (reader, writer) = pipe().unwrap();
+        drop(reader);
+        let status = Command::new(uncbv_executable())
+            .args(["list", "tests/twic1134.cbv", "--format", format])
+            .stdout(writer)
+            .status()
+            .unwrap();
+        assert_eq!(Some(0), status.code());
+    }
+}
+
+#[test]
+fn overwrite_policies() {
+    fn try_extract(dir_name: &str, args: &[&str], answers: &str) {
+        let mut process = Command::new(uncbv_executable());
+        let mut child =
+            process.args(["extract", "tests/small.cbv", "-o", dir_name])
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped()) // NOTE: hide the questions.
+                .stderr(Stdio::null()) // NOTE: hide the files which were not overwritten.
+                .spawn()
+                .unwrap();
+        write!(child.stdin.as_mut().unwrap(), "{}", answers).unwrap();
+        child.wait().unwrap();
+    }
+
+    fn modified_time(dir_name: &str, filename: &str) -> SystemTime {
+        metadata(format!("{}/{}", dir_name, filename)).unwrap().modified().unwrap()
+    }
+
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let expected_files = get_file_recursives("tests/small");
+    try_extract(dir_name, &[], "");
+    assert_eq!(expected_files, get_file_recursives(dir_name));
+
+    // Every file is asked for: override the first one, then none.
+    let cbh_time = modified_time(dir_name, "small.cbh");
+    let ini_time = modified_time(dir_name, "small.ini");
+    let cbg_time = modified_time(dir_name, "small.cbg");
+    try_extract(dir_name, &[], "y\nn\nnone\n");
+    assert!(cbh_time != modified_time(dir_name, "small.cbh"));
+    assert_eq!(ini_time, modified_time(dir_name, "small.ini"));
+    assert_eq!(cbg_time, modified_time(dir_name, "small.cbg"));
+
+    try_extract(dir_name, &[], "n\nall\n");
+    assert!(ini_time != modified_time(dir_name, "small.ini"));
+    assert!(cbg_time != modified_time(dir_name, "small.cbg"));
+
+    let cbg_time = modified_time(dir_name, "small.cbg");
+    try_extract(dir_name, &["--skip-existing"], "");
+    assert_eq!(cbg_time, modified_time(dir_name, "small.cbg"));
+
+    // The existing files are newer than the files of the archive.
+    try_extract(dir_name, &["--keep-newer"], "");
+    assert_eq!(cbg_time, modified_time(dir_name, "small.cbg"));
+
+    try_extract(dir_name, &["--overwrite"], "");
+    assert!(cbg_time != modified_time(dir_name, "small.cbg"));
+
+    try_extract(dir_name, &["--rename"], "");
+    try_extract(dir_name, &["--rename"], "");
+    let files = get_file_recursives(dir_name);
+    assert_eq!(expected_files.len() * 3, files.len());
+    assert_file("tests/small/small.cbh".to_string(), format!("{}/small.1.cbh", dir_name));
+    assert_file("tests/small/small.cbh".to_string(), format!("{}/small.2.cbh", dir_name));
+
+    // The decrypted archive follows the policy of the extracted files.
+    let decrypted = format!("{}/small.cbv", dir_name);
+    File::create(&decrypted).unwrap().write_all(b"existing").unwrap();
+    for policy in &["--skip-existing", "--keep-newer", "--rename"] {
+        run_uncbv(&["extract", "tests/small.cbz", "-o", dir_name, "--keep-decrypted", "--password", DEFAULT_PASSWORD,
+            policy]);
+        let mut content = String::new();
+        File::open(&decrypted).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!("existing", content);
+    }
+    assert!(Archive::open(format!("{}/small.1.cbv", dir_name)).is_ok());
+
+    // A file appearing twice in the archive is overwritten by its last entry.
+    let duplicates = format!("{}/duplicates.cbv", dir_name);
+    let mut writer = ArchiveWriter::new(File::create(&duplicates).unwrap());
+    writer.add("x.txt", &b"first"[..]).unwrap();
+    writer.add("x.txt", &b"second"[..]).unwrap();
+    writer.finish().unwrap();
+    for policy in &["--keep-newer", "--overwrite", "--skip-existing"] {
+        let output_dir = format!("{}/duplicates{}", dir_name, policy);
+        create_dir_all(&output_dir).unwrap();
+        assert_eq!(Some(0), uncbv_exit_code(&["extract", &duplicates, "-o", &output_dir, policy]));
+        let mut content = String::new();
+        File::open(format!("{}/x.txt", output_dir)).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(if *policy == "--skip-existing" { "first" } else { "second" }, content);
+    }
+}
+
+#[test]
+fn password_list() {
+    let temp_dir = TempDir::new();
+    let password_list = format!("{}/passwords", temp_dir.as_str());
+    write!(File::create(&password_list).unwrap(), "foo\n\nlong password\r\n{}\nbar\n", DEFAULT_PASSWORD).unwrap();
+
+    // The line of the password is shown on stderr to keep the listing parseable.
+    let output = run_uncbv_stderr(&["list", "tests/small.cbz", "--password-list", &password_list]);
+    assert_eq!(format!("Using the password on line 4 of {}\n", password_list), output);
+    let output = run_uncbv(&["list", "tests/small.cbz", "--password-list", &password_list]);
+    let mut output_files: Vec<_> = output.lines().map(str::to_string).collect();
+    output_files.sort();
+    assert_eq!(get_file_recursives("tests/small"), output_files);
+
+    let output_dir = format!("{}/output", temp_dir.as_str());
+    create_dir_all(&output_dir).unwrap();
+    Command::new(uncbv_executable())
+        .args(["extract", "tests/small.cbz", "--password-list", &password_list, "-o", &output_dir])
+        .output()
+        .unwrap();
+    for file in get_file_recursives("tests/small") {
+        assert_file(format!("tests/small/{}", file), format!("{}/{}", output_dir, file));
+    }
+
+    writeln!(File::create(&password_list).unwrap(), "foo").unwrap();
+    let output = run_uncbv_stderr(&["list", "tests/small.cbz", "--password-list", &password_list]);
+    assert!(output.contains("None of the passwords"));
+}
+
+#[test]
+fn password_sources() {
+    let temp_dir = TempDir::new();
+    let password_file = format!("{}/password", temp_dir.as_str());
+    writeln!(File::create(&password_file).unwrap(), "{}", DEFAULT_PASSWORD).unwrap();
+    let expected_files = get_file_recursives("tests/small");
+
+    let check_list = |process: &mut Command| {
+        let output = process.stdin(Stdio::null()).output().unwrap();
+        let mut output_files: Vec<_> = String::from_utf8(output.stdout).unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        output_files.sort();
+        assert_eq!(expected_files, output_files);
+    };
+    check_list(Command::new(uncbv_executable()).args(["list", "tests/small.cbz", "--password", DEFAULT_PASSWORD]));
+    check_list(Command::new(uncbv_executable()).args(["list", "tests/small.cbz", "--password-file", &password_file]));
+    check_list(Command::new(uncbv_executable()).args(["list", "tests/small.cbz"])
+        .env("UNCBV_PASSWORD", DEFAULT_PASSWORD));
+
+    let mut child = Command::new(uncbv_executable())
+        .args(["list", "tests/small.cbz", "--password-fd", "0"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(child.stdin.as_mut().unwrap(), "{}", DEFAULT_PASSWORD).unwrap();
+    let output = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    assert_eq!(expected_files.len(), output.lines().count());
+
+    let output_file = format!("{}/small.cbv", temp_dir.as_str());
+    Command::new(uncbv_executable())
+        .args(["decrypt", "tests/small.cbz", "-o", &output_file, "--password", DEFAULT_PASSWORD])
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+    assert_file("tests/decrypted_small.cbv".to_string(), output_file);
+
+    let output = run_uncbv_stderr(&["list", "tests/small.cbz", "--password-file", "tests/missing"]);
+    assert!(output.starts_with("tests/small.cbz: "));
+}
+
+#[test]
+fn test_archive() {
+    let mut process = Command::new(uncbv_executable());
+    let output = process.args(["test", "tests/twic1134.cbv"]).output().unwrap();
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(13, output.lines().filter(|line| line.starts_with("OK")).count());
+
+    let mut bytes = vec![];
+    File::open("tests/twic1134.cbv").unwrap().read_to_end(&mut bytes).unwrap();
+    let temp_dir = TempDir::new();
+    let filename = format!("{}/corrupted.cbv", temp_dir.as_str());
+
+    let mut corrupted = bytes.clone();
+    let index = corrupted.len() - 3;
+    corrupted[index] ^= 0xFF;
+    let truncated = &bytes[.. bytes.len() - 10];
+    for (content, error) in &[(&corrupted[..], "Bad checksum"), (truncated, "compressed size")] {
+        File::create(&filename).unwrap().write_all(content).unwrap();
+        let mut process = Command::new(uncbv_executable());
+        let output = process.args(["test", &filename]).output().unwrap();
+        assert!(!output.status.success());
+        let output = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(12, output.lines().filter(|line| line.starts_with("OK")).count());
+        let failure = output.lines().find(|line| line.starts_with("FAIL")).unwrap();
+        assert!(failure.contains("twic1134.cbl") && failure.contains(error));
+    }
+
+    // The errors are structured in the other formats.
+    let output = run_uncbv(&["test", &filename, "--format", "json"]);
+    let results: Value = serde_json::from_str(&output).unwrap();
+    let error = &results["files"][12]["error"];
+    assert_eq!("CompressedSize", error["kind"]);
+    assert_eq!("twic1134.cbl", error["entry"]);
+    assert_eq!(28, error["actual"]);
+    assert_eq!(38, error["expected"]);
+    let output = run_uncbv(&["test", &filename, "--format", "csv"]);
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!("filename,status,error,kind,entry,block_index,offset,actual,expected,reason", lines[0]);
+    assert_eq!("twic1134.cbh,OK,,,,,,,,", lines[1]);
+    assert_eq!("twic1134.cbl,FAIL,The compressed size of twic1134.cbl is 28 bytes instead of 38,CompressedSize,\
+        twic1134.cbl,,,28,38,", lines[13]);
+}
+
+#[test]
+fn wrong_password() {
+    let temp_dir = TempDir::new();
+    let dir_name = temp_dir.as_str();
+    let decrypted_file = format!("{}/decrypted/small.cbv", dir_name);
+    let commands: Vec<Vec<&str>> = vec![
+        vec!["extract", "tests/small.cbz", "-o", dir_name, "--no-confirm"],
+        vec!["extract", "tests/small.cbz", "-o", dir_name, "--keep-decrypted", "--no-confirm"],
+        vec!["decrypt", "tests/small.cbz", "-o", &decrypted_file, "--no-confirm"],
+        vec!["list", "tests/small.cbz"],
+    ];
+    for mut args in commands {
+        args.extend_from_slice(&["--password", "wrong"]);
+        let output = run_uncbv_stderr(&args);
+        assert_eq!("tests/small.cbz: Wrong password, or not an encrypted CBV archive\n", output);
+        assert!(get_file_recursives(dir_name).is_empty());
+    }
+}
+
 struct TempDir {
     path: PathBuf,
     string: OsString,
@@ -321,6 +1586,27 @@ impl Drop for TempDir {
     }
 }
 
+/// Create an archive containing the file block.bin, made of a single compressed block whose content
+/// (after the compression flag) is `lz_data`.
+fn archive_with_block(lz_data: &[u8], decompressed_size: usize) -> Vec<u8> {
+    let mut payload = vec![0x01];
+    payload.extend_from_slice(lz_data);
+    let mut block = vec![];
+    block.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    block.extend_from_slice(&checksum(&payload).to_le_bytes());
+    block.extend_from_slice(&payload);
+
+    let mut record = vec![0; 173];
+    record[..9].copy_from_slice(b"block.bin");
+    record[132..136].copy_from_slice(&(block.len() as i32).to_le_bytes());
+    record[136..140].copy_from_slice(&(decompressed_size as i32).to_le_bytes());
+
+    let mut bytes = vec![0x08, 0x00, 0x01, 0x00, 173, 0x00, 0x03, 0x00];
+    bytes.extend_from_slice(&record);
+    bytes.extend_from_slice(&block);
+    bytes
+}
+
 fn assert_file(expected_file_name: String, actual_file_name: String) {
     assert!(expected_file_name != actual_file_name);
     let mut expected_file = File::open(expected_file_name).unwrap();
@@ -436,6 +1722,24 @@ fn get_file_recursives(directory: &str) -> Vec<String> {
     expected_files
 }
 
+/// Create an archive containing the file text.txt, made of a single small Huffman-encoded block.
+/// NOTE: the test archives do not contain small Huffman-encoded blocks.
+fn huffman_archive() -> Vec<u8> {
+    let mut writer = ArchiveWriter::new(vec![]);
+    let mut state = 1_u32;
+    let text: Vec<u8> = (0 .. 600)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            b"aaaaaaaabbbbccd e"[(state >> 16) as usize % 17]
+        })
+        .collect();
+    writer.add("text.txt", &text[..]).unwrap();
+    let bytes = writer.finish().unwrap();
+    let archive = Archive::from_bytes(bytes.clone()).unwrap();
+    assert!(archive.entry("text.txt").unwrap().block_flags().unwrap()[0].huffman_encoded);
+    bytes
+}
+
 fn list(filename: &str) {
     let name = format!("tests/{}", filename);
     let mut process = Command::new(uncbv_executable());
@@ -505,6 +1809,32 @@ fn others() -> (Vec<String>, Vec<String>) {
     (files_to_decrypt, files_to_extract)
 }
 
+fn run_uncbv(args: &[&str]) -> String {
+    let output = Command::new(uncbv_executable())
+        .args(args)
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn run_uncbv_stderr(args: &[&str]) -> String {
+    let output = Command::new(uncbv_executable())
+        .args(args)
+        .output()
+        .unwrap();
+    String::from_utf8(output.stderr).unwrap()
+}
+
+fn uncbv_exit_code(args: &[&str]) -> Option<i32> {
+    Command::new(uncbv_executable())
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
 #[cfg(debug_assertions)]
 fn uncbv_executable() -> String {
     "target/debug/uncbv".to_string()